@@ -0,0 +1,289 @@
+use crate::qsbr::{GpSnapshot, Qsbr, QsbrGuard, QsbrSleeper, QsbrThreadHandle};
+use crate::utils::Lock;
+use crate::{RcuHandle, RegisterError, SleepingRcu, RCU};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{self, AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll, Waker};
+
+/// AsyncRcu, Qsbr for async runtimes
+/// each executor worker thread registers a handle, and since a task can only be suspended at an
+/// await point, every time a task yields its worker is in a quiescent state (as long as no guard
+/// is held across an await). Wrapping a task in AsyncThreadHandle::quiescent reports those
+/// quiescent states, and AsyncThreadHandle::synchronize waits for a grace period without
+/// blocking the worker
+///
+/// worker threads should put their handle to sleep while parked, otherwise syncs will wait until
+/// the worker runs again
+#[derive(Debug)]
+pub struct AsyncRcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    qsbr: Qsbr<L>,
+    /// tasks waiting on a grace period, keyed by Synchronize::id so repolling a future replaces
+    /// its waker instead of adding another one, along with the id of the handle it waits from
+    wakers: Mutex<HashMap<u64, (u64, Waker)>>,
+    /// next Synchronize::id
+    next_waiter: AtomicU64,
+    /// set when wakers isn't empty, so quiescent states don't need to take the mutex
+    has_wakers: AtomicBool,
+}
+
+impl<L> Default for AsyncRcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L> AsyncRcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// wake every task waiting on a grace period, so they can recheck it, except the ones
+    /// waiting from `reporter`'s handle, since a grace period never waits on its own thread
+    /// otherwise handle.quiescent(handle.synchronize()) would wake itself on every poll
+    fn wake_syncs(&self, reporter: u64) {
+        //Ordering: the quiescent state needs to be visible to woken tasks, pairs with the
+        //fence in Synchronize::poll
+        atomic::fence(Ordering::SeqCst);
+        if !self.has_wakers.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut wakers = self.wakers.lock().unwrap();
+        let mut woken = Vec::new();
+        wakers.retain(|_, (owner, waker)| {
+            if *owner == reporter {
+                return true;
+            }
+            woken.push(waker.clone());
+            false
+        });
+        self.has_wakers.store(!wakers.is_empty(), Ordering::Relaxed);
+        drop(wakers);
+        woken.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl<L> RCU for AsyncRcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    type Handle<'a> = AsyncThreadHandle<'a, L> where L: 'a;
+    /// create a new AsyncRcu
+    fn new() -> Self {
+        Self {
+            qsbr: Qsbr::new(),
+            wakers: Mutex::new(HashMap::new()),
+            next_waiter: AtomicU64::new(0),
+            has_wakers: AtomicBool::new(false),
+        }
+    }
+    /// register a new executor worker with AsyncRcu
+    /// takes an unique id for this handle, fails if id is already registered
+    fn register(&self, id: u64) -> Result<Self::Handle<'_>, RegisterError> {
+        Ok(AsyncThreadHandle {
+            handle: self.qsbr.register(id)?,
+            rcu: self,
+        })
+    }
+    fn domain_id(&self) -> u64 {
+        self.qsbr.domain_id()
+    }
+}
+
+///created via AsyncRcu::register(), one per executor worker thread
+pub struct AsyncThreadHandle<'a, L>
+where
+    L: for<'b> Lock<'b>,
+    Self: 'a,
+{
+    handle: QsbrThreadHandle<'a, L>,
+    rcu: &'a AsyncRcu<L>,
+}
+
+impl<'a, L> AsyncThreadHandle<'a, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    /// returns a future that resolves once a grace period has passed, without blocking the
+    /// worker. The awaiting task is treated as quiescent, so must not hold a guard across the await
+    ///
+    /// must be polled on this handle's worker
+    pub fn synchronize(&self) -> Synchronize<'_, 'a, L> {
+        Synchronize {
+            snapshot: self.handle.get_state(),
+            handle: self,
+            id: self.rcu.next_waiter.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// wrap `fut` so that this worker reports a quiescent state every time it yields
+    ///
+    /// must be polled on this handle's worker
+    pub fn quiescent<F>(&self, fut: F) -> Quiescent<'_, 'a, F, L>
+    where
+        F: Future,
+    {
+        Quiescent { fut, handle: self }
+    }
+
+    /// see QsbrThreadHandle::call_rcu, callbacks are run from quiescent_state()
+    pub fn call_rcu<F>(&mut self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.handle.call_rcu(f);
+    }
+
+    fn mark_quiescent(&self) {
+        self.handle.mark_quiescent();
+        self.rcu.wake_syncs(self.handle.id());
+    }
+}
+
+impl<'a, L> RcuHandle<'a> for AsyncThreadHandle<'a, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    type Guard<'b> = QsbrGuard<'b, L> where Self: 'a, 'a: 'b;
+    type Sleeper<'b> = AsyncSleeper<'b, L> where Self: 'a, 'a: 'b;
+    fn sleep(self) -> Self::Sleeper<'a> {
+        let rcu = self.rcu;
+        let id = self.handle.id();
+        let sleeper = self.handle.sleep();
+        rcu.wake_syncs(id);
+        Self::Sleeper { sleeper, rcu }
+    }
+    /// read starts an rcu critical section, which must not be held across an await
+    fn read(&self) -> Self::Guard<'_> {
+        self.handle.read()
+    }
+    fn quiescent_state(&mut self) {
+        self.handle.quiescent_state();
+        self.rcu.wake_syncs(self.handle.id());
+    }
+    fn quiescent_sync(&mut self) {
+        self.handle.quiescent_sync();
+        self.rcu.wake_syncs(self.handle.id());
+    }
+    /// blocks the worker until a grace period has passed, prefer synchronize().await
+    fn sync(&self) {
+        self.handle.sync();
+    }
+    fn is_reading(&self) -> bool {
+        self.handle.is_reading()
+    }
+    fn domain_id(&self) -> u64 {
+        self.handle.domain_id()
+    }
+}
+
+pub struct AsyncSleeper<'a, L>
+where
+    L: for<'l> Lock<'l>,
+{
+    sleeper: QsbrSleeper<'a, L>,
+    rcu: &'a AsyncRcu<L>,
+}
+
+impl<'a, L> SleepingRcu<'a> for AsyncSleeper<'a, L>
+where
+    L: for<'l> Lock<'l>,
+{
+    type Handle = AsyncThreadHandle<'a, L>;
+    fn wake(self) -> Self::Handle {
+        AsyncThreadHandle {
+            handle: self.sleeper.wake(),
+            rcu: self.rcu,
+        }
+    }
+}
+
+/// future returned by AsyncThreadHandle::synchronize
+pub struct Synchronize<'h, 'a, L>
+where
+    L: for<'l> Lock<'l>,
+{
+    handle: &'h AsyncThreadHandle<'a, L>,
+    snapshot: GpSnapshot,
+    /// key of this future's waker in AsyncRcu::wakers
+    id: u64,
+}
+
+impl<L> Future for Synchronize<'_, '_, L>
+where
+    L: for<'l> Lock<'l>,
+{
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        if this.handle.handle.gp_elapsed(&this.snapshot) {
+            return Poll::Ready(());
+        }
+        let rcu = this.handle.rcu;
+        let mut wakers = rcu.wakers.lock().unwrap();
+        match wakers.get_mut(&this.id) {
+            Some((_, waker)) if waker.will_wake(cx.waker()) => {}
+            Some((_, waker)) => waker.clone_from(cx.waker()),
+            None => {
+                wakers.insert(this.id, (this.handle.handle.id(), cx.waker().clone()));
+            }
+        }
+        drop(wakers);
+        rcu.has_wakers.store(true, Ordering::Relaxed);
+        //Ordering: either we see the quiescent state, or it sees has_wakers and wakes us
+        atomic::fence(Ordering::SeqCst);
+        // a quiescent state could have happened before the waker was registered
+        if this.handle.handle.gp_elapsed(&this.snapshot) {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl<L> Drop for Synchronize<'_, '_, L>
+where
+    L: for<'l> Lock<'l>,
+{
+    /// forget the waker of a future dropped before it completed
+    fn drop(&mut self) {
+        if self.handle.rcu.has_wakers.load(Ordering::Relaxed) {
+            self.handle.rcu.wakers.lock().unwrap().remove(&self.id);
+        }
+    }
+}
+
+/// future returned by AsyncThreadHandle::quiescent
+pub struct Quiescent<'h, 'a, F, L>
+where
+    L: for<'l> Lock<'l>,
+{
+    fut: F,
+    handle: &'h AsyncThreadHandle<'a, L>,
+}
+
+impl<F, L> Future for Quiescent<'_, '_, F, L>
+where
+    F: Future,
+    L: for<'l> Lock<'l>,
+{
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let handle = self.handle;
+        // Saftey: fut is never moved out of self
+        let fut = unsafe { self.map_unchecked_mut(|s| &mut s.fut) };
+        let ret = fut.poll(cx);
+        // the task is suspended at an await point, so can't be in a critical section, unless it
+        // holds a guard across the await, in which case it must not be reported
+        if ret.is_pending() && !handle.is_reading() {
+            handle.mark_quiescent();
+        }
+        ret
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{RcuHandle, RCU};
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// ids handed out to automatically registered threads, ids from 2^63 up are reserved for them
+static NEXT_ID: AtomicU64 = AtomicU64::new(1 << 63);
+
+/// address and type of the rcu a handle is registered with
+type HandleKey = (usize, TypeId);
+
+thread_local! {
+    /// this thread's handles, dropped, and so unregistered, when the thread exits
+    static HANDLES: RefCell<Vec<(HandleKey, Rc<dyn Any>)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// "bullet-proof" registration, for code that can't pass a handle around
+///
+/// the first time a thread uses `rcu` through this module it is registered with an internally
+/// generated id, and the handle is kept in a thread local until the thread exits
+///
+/// readers never report quiescent states by themselves, so this is best used with a flavor
+/// where readers track their own critical sections like Mb or Memb. With Qsbr every thread
+/// still needs to call quiescent_state through with_handle
+fn handle<R>(rcu: &'static R) -> Rc<RefCell<R::Handle<'static>>>
+where
+    R: RCU + Sync,
+    R::Handle<'static>: 'static,
+{
+    let key: HandleKey = (rcu as *const R as usize, TypeId::of::<R>());
+    let found = HANDLES.with_borrow(|handles| {
+        handles
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, h)| h.clone())
+    });
+    let handle = found.unwrap_or_else(|| {
+        // ids from 2^63 up should only be used here, but retry in case a caller used one
+        let handle: R::Handle<'static> = loop {
+            if let Ok(handle) = rcu.register(NEXT_ID.fetch_add(1, Ordering::Relaxed)) {
+                break handle;
+            }
+        };
+        let handle: Rc<dyn Any> = Rc::new(RefCell::new(handle));
+        HANDLES.with_borrow_mut(|handles| handles.push((key, handle.clone())));
+        handle
+    });
+    match handle.downcast() {
+        Ok(handle) => handle,
+        Err(_) => unreachable!("handles are keyed by their rcu's TypeId"),
+    }
+}
+
+/// run `f` inside a read side critical section of `rcu`, registering this thread first if
+/// needed. Can be nested
+pub fn read<R, F, T>(rcu: &'static R, f: F) -> T
+where
+    R: RCU + Sync,
+    R::Handle<'static>: 'static,
+    F: for<'g> FnOnce(&<R::Handle<'static> as RcuHandle<'static>>::Guard<'g>) -> T,
+{
+    let handle = handle(rcu);
+    let handle = handle.borrow();
+    let guard = handle.read();
+    f(&guard)
+}
+
+/// run `f` with this thread's handle for `rcu`, registering this thread first if needed
+/// used for writer side operations like sync, or quiescent_state with Qsbr
+///
+/// panics if called from inside read or with_handle for the same rcu
+pub fn with_handle<R, F, T>(rcu: &'static R, f: F) -> T
+where
+    R: RCU + Sync,
+    R::Handle<'static>: 'static,
+    F: FnOnce(&mut R::Handle<'static>) -> T,
+{
+    let handle = handle(rcu);
+    let mut handle = handle.borrow_mut();
+    f(&mut handle)
+}
@@ -1,2 +1,54 @@
+pub mod rcucell;
+pub mod rcuhashmap;
 /// concurrent data structures
 pub mod rculist;
+
+use crate::RcuGuard;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// the rcu instance a structure is protected by, either bound up front with `bound`, or lazily
+/// to the domain of the first guard or handle the structure is used with. Using it with a guard
+/// or handle from another instance afterwards panics. Lazily bound domains are only checked in
+/// debug builds since it costs an atomic per call
+#[derive(Debug, Default)]
+pub(crate) struct Domain {
+    /// 0 until bound
+    id: AtomicU64,
+    /// bound up front, checked in release builds too
+    strict: bool,
+}
+
+impl Domain {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn bound(domain_id: u64) -> Self {
+        Self {
+            id: AtomicU64::new(domain_id),
+            strict: true,
+        }
+    }
+
+    pub(crate) fn check(&self, domain_id: u64) {
+        if !self.strict && !cfg!(debug_assertions) {
+            return;
+        }
+        if let Err(bound) =
+            self.id
+                .compare_exchange(0, domain_id, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            assert_eq!(
+                bound, domain_id,
+                "rcu guard or handle is from a different rcu instance than this structure"
+            );
+        }
+    }
+
+    pub(crate) fn check_guard<'g, G>(&self, guard: &G)
+    where
+        G: RcuGuard<'g>,
+    {
+        self.check(guard.domain_id());
+    }
+}
@@ -0,0 +1,135 @@
+use super::Domain;
+use crate::{RcuHandle, RCU};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+/// RcuCell, a single rcu protected pointer
+/// readers get a reference to the current value for as long as their guard lives, writers
+/// publish a new value and get the old one back once no reader can still see it
+#[derive(Debug)]
+pub struct RcuCell<T, R>
+where
+    R: RCU,
+{
+    ptr: AtomicPtr<T>,
+    domain: Domain,
+    _rcu: PhantomData<R>,
+    // AtomicPtr is always Send + Sync, but handing out &T needs T: Sync and replace moves T
+    // between threads, see the impls below
+    _value: PhantomData<*const T>,
+}
+
+unsafe impl<T, R> Send for RcuCell<T, R>
+where
+    T: Send,
+    R: RCU,
+{
+}
+
+unsafe impl<T, R> Sync for RcuCell<T, R>
+where
+    T: Send + Sync,
+    R: RCU,
+{
+}
+
+impl<T, R> Drop for RcuCell<T, R>
+where
+    R: RCU,
+{
+    fn drop(&mut self) {
+        // Saftey: &mut self means no readers are left
+        let _ = unsafe { Box::from_raw(*self.ptr.get_mut()) };
+    }
+}
+
+impl<T, R> Default for RcuCell<T, R>
+where
+    T: Default,
+    R: RCU,
+{
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T, R> RcuCell<T, R>
+where
+    R: RCU,
+{
+    /// create a cell holding `value`
+    pub fn new(value: T) -> Self {
+        Self {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            domain: Domain::new(),
+            _rcu: PhantomData,
+            _value: PhantomData,
+        }
+    }
+
+    /// get the current value, which stays valid for as long as the guard lives
+    pub fn read<'a, 'b, 'g>(
+        &'a self,
+        guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'g>,
+    ) -> &'a T
+    where
+        'b: 'g,
+        'g: 'a,
+    {
+        self.domain.check_guard(guard);
+        //Ordering: pairs with the Release in replace/update, so the value is fully initialized
+        unsafe { &*self.ptr.load(Ordering::Acquire) }
+    }
+
+    /// publish `value`, then wait for a grace period and return the old value
+    ///
+    /// WARNING: since this method calls `handle.quiescent_sync()` it can cause a deadlock
+    pub fn replace(&self, value: T, handle: &mut R::Handle<'_>) -> T {
+        self.domain.check(handle.domain_id());
+        let new = Box::into_raw(Box::new(value));
+        //Ordering: Release publishes new, Acquire so the old value is visible to us
+        let old = self.ptr.swap(new, Ordering::AcqRel);
+        handle.quiescent_sync();
+        // Saftey: old was unpublished before the grace period, so no readers are left
+        *unsafe { Box::from_raw(old) }
+    }
+
+    /// read-copy-update, publish `f(old)` then wait for a grace period and return old
+    ///
+    /// `f` may be called more than once if other writers update the cell concurrently
+    /// WARNING: since this method calls `handle.quiescent_sync()` it can cause a deadlock
+    pub fn update<F>(&self, mut f: F, handle: &mut R::Handle<'_>) -> T
+    where
+        F: FnMut(&T) -> T,
+    {
+        self.domain.check(handle.domain_id());
+        let guard = handle.read();
+        let mut old = self.ptr.load(Ordering::Acquire);
+        let mut new = Box::into_raw(Box::new(f(unsafe { &*old })));
+        loop {
+            match self
+                .ptr
+                .compare_exchange(old, new, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(current) => {
+                    old = current;
+                    // Saftey: new was never published, it is freed before calling `f` again so
+                    // a panic in `f` doesn't leak it
+                    drop(unsafe { Box::from_raw(new) });
+                    new = Box::into_raw(Box::new(f(unsafe { &*old })));
+                }
+            }
+        }
+        drop(guard);
+        handle.quiescent_sync();
+        // Saftey: old was unpublished before the grace period, so no readers are left
+        *unsafe { Box::from_raw(old) }
+    }
+
+    /// get a mutable reference to the current value, no readers can exist since self is borrowed
+    /// mutably
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut **self.ptr.get_mut() }
+    }
+}
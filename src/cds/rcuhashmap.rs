@@ -0,0 +1,536 @@
+use super::Domain;
+use crate::utils::Lock;
+use crate::{RcuHandle, RCU};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::marker::PhantomData;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// number of buckets a new map starts with, must be a power of 2
+const INITIAL_BUCKETS: usize = 16;
+/// average number of entries per bucket before the table is doubled
+const MAX_LOAD: usize = 2;
+
+/// RcuHashMap, a split-ordered hash map (see Shalev & Shavit, "Split-Ordered Lists: Lock-Free
+/// Extensible Hash Tables", and liburcu's rculfhash)
+///
+/// every entry lives in a single list sorted by the bit reversed hash, and each bucket is a
+/// shortcut into that list via a dummy node. Doubling the table only adds new dummy nodes, so
+/// entries never move and readers never block or miss an entry while the map resizes.
+/// Writers lock the dummy node in front of the part of the list they modify, so writers in
+/// different buckets don't contend
+pub struct RcuHashMap<K, V, R, L>
+where
+    K: Hash + Eq,
+    R: RCU,
+    L: for<'a> Lock<'a>,
+{
+    /// dummy node for bucket 0, the start of the list
+    head: *mut Node<K, V, L>,
+    table: AtomicPtr<Table<K, V, L>>,
+    /// serializes resizes
+    resize_lock: L,
+    len: AtomicUsize,
+    hasher: RandomState,
+    domain: Domain,
+    _rcu: PhantomData<R>,
+}
+
+// raw pointers stop Send and Sync from being derived, the map owns its K and V and hands out
+// shared references to them from any thread
+unsafe impl<K, V, R, L> Send for RcuHashMap<K, V, R, L>
+where
+    K: Hash + Eq + Send,
+    V: Send,
+    R: RCU,
+    L: for<'a> Lock<'a> + Send,
+{
+}
+
+unsafe impl<K, V, R, L> Sync for RcuHashMap<K, V, R, L>
+where
+    K: Hash + Eq + Send + Sync,
+    V: Send + Sync,
+    R: RCU,
+    L: for<'a> Lock<'a> + Send + Sync,
+{
+}
+
+struct Node<K, V, L> {
+    /// position in the list, the bit reversed hash for entries, or bit reversed bucket index
+    /// for dummies. The lowest bit is set for entries so dummies always sort first
+    so_key: u64,
+    next: AtomicPtr<Node<K, V, L>>,
+    kind: NodeKind<K, V, L>,
+}
+
+enum NodeKind<K, V, L> {
+    /// start of a bucket, the lock protects the list from here up to the next dummy
+    Dummy(L),
+    Entry(K, V),
+}
+
+impl<K, V, L> Node<K, V, L>
+where
+    L: for<'a> Lock<'a>,
+{
+    fn is_dummy(&self) -> bool {
+        matches!(self.kind, NodeKind::Dummy(_))
+    }
+
+    fn lock(&self) -> <L as Lock<'_>>::Guard {
+        match &self.kind {
+            NodeKind::Dummy(lock) => lock.lock(),
+            NodeKind::Entry(..) => unreachable!("only dummy nodes can be locked"),
+        }
+    }
+
+    fn next(&self) -> Option<&Self> {
+        //Ordering: pairs with the Release when a node is published
+        unsafe { self.next.load(Ordering::Acquire).as_ref() }
+    }
+}
+
+struct Table<K, V, L> {
+    /// dummy node for each bucket, null until the bucket is first written to
+    buckets: Box<[AtomicPtr<Node<K, V, L>>]>,
+    /// the table this one replaced, readers may still be using it so it is only freed when
+    /// the map is dropped
+    prev: *mut Table<K, V, L>,
+}
+
+/// a node, and the node after it
+type Position<'a, K, V, L> = (&'a Node<K, V, L>, Option<&'a Node<K, V, L>>);
+
+/// position of an entry in the list
+fn so_regular(hash: u64) -> u64 {
+    (hash | 1 << 63).reverse_bits()
+}
+
+/// position of a bucket's dummy node in the list
+fn so_dummy(bucket: usize) -> u64 {
+    (bucket as u64).reverse_bits()
+}
+
+/// the bucket that gets split to create `bucket`
+fn parent(bucket: usize) -> usize {
+    bucket & !(1 << (usize::BITS - 1 - bucket.leading_zeros()))
+}
+
+impl<K, V, R, L> Drop for RcuHashMap<K, V, R, L>
+where
+    K: Hash + Eq,
+    R: RCU,
+    L: for<'a> Lock<'a>,
+{
+    fn drop(&mut self) {
+        let mut node = self.head;
+        while !node.is_null() {
+            let next = unsafe { (*node).next.load(Ordering::Relaxed) };
+            let _ = unsafe { Box::from_raw(node) };
+            node = next;
+        }
+        let mut table = self.table.load(Ordering::Relaxed);
+        while !table.is_null() {
+            let prev = unsafe { (*table).prev };
+            let _ = unsafe { Box::from_raw(table) };
+            table = prev;
+        }
+    }
+}
+
+impl<K, V, R, L> fmt::Debug for RcuHashMap<K, V, R, L>
+where
+    K: Hash + Eq,
+    R: RCU,
+    L: for<'a> Lock<'a>,
+{
+    /// entries can only be read under a guard, so only the shape of the map is printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RcuHashMap")
+            .field("len", &self.len())
+            .field("buckets", &self.table().buckets.len())
+            .field("domain", &self.domain)
+            .finish()
+    }
+}
+
+impl<K, V, R, L> Default for RcuHashMap<K, V, R, L>
+where
+    K: Hash + Eq,
+    R: RCU,
+    L: for<'a> Lock<'a>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V, R, L> RcuHashMap<K, V, R, L>
+where
+    K: Hash + Eq,
+    R: RCU,
+    L: for<'a> Lock<'a>,
+{
+    /// create an empty map
+    pub fn new() -> Self {
+        let head = Box::into_raw(Box::new(Node {
+            so_key: so_dummy(0),
+            next: AtomicPtr::new(null_mut()),
+            kind: NodeKind::Dummy(L::new()),
+        }));
+        let buckets: Box<[_]> = (0..INITIAL_BUCKETS)
+            .map(|_| AtomicPtr::new(null_mut()))
+            .collect();
+        buckets[0].store(head, Ordering::Relaxed);
+        Self {
+            head,
+            table: AtomicPtr::new(Box::into_raw(Box::new(Table {
+                buckets,
+                prev: null_mut(),
+            }))),
+            resize_lock: L::new(),
+            len: AtomicUsize::new(0),
+            hasher: RandomState::new(),
+            domain: Domain::new(),
+            _rcu: PhantomData,
+        }
+    }
+
+    /// number of entries in the map, may be stale by the time it returns
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// whether the map has no entries, may be stale by the time it returns
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn table(&self) -> &Table<K, V, L> {
+        //Ordering: pairs with the Release in resize
+        unsafe { &*self.table.load(Ordering::Acquire) }
+    }
+
+    /// the closest initialized dummy node at or before `hash`'s bucket, never blocks
+    fn bucket_for_read(&self, hash: u64) -> &Node<K, V, L> {
+        let table = self.table();
+        let mut bucket = hash as usize & (table.buckets.len() - 1);
+        loop {
+            let node = table.buckets[bucket].load(Ordering::Acquire);
+            if let Some(node) = unsafe { node.as_ref() } {
+                return node;
+            }
+            bucket = parent(bucket);
+        }
+    }
+
+    /// the dummy node for `bucket` in `table`, inserting it into the list if needed
+    fn bucket_for_write<'a>(
+        &'a self,
+        table: &'a Table<K, V, L>,
+        bucket: usize,
+    ) -> &'a Node<K, V, L> {
+        let node = table.buckets[bucket].load(Ordering::Acquire);
+        if let Some(node) = unsafe { node.as_ref() } {
+            return node;
+        }
+        let parent = self.bucket_for_write(table, parent(bucket));
+        let so_key = so_dummy(bucket);
+        let (guard, segment) = self.lock_segment(parent, so_key);
+        let (prev, next) = Self::find_position(segment, so_key);
+        let node = match next {
+            // initialized through a different table
+            Some(next) if next.so_key == so_key => next as *const _ as *mut _,
+            _ => {
+                let node = Box::into_raw(Box::new(Node {
+                    so_key,
+                    next: AtomicPtr::new(next.map_or(null_mut(), |n| n as *const _ as *mut _)),
+                    kind: NodeKind::Dummy(L::new()),
+                }));
+                //Ordering: node needs to be initialized before readers can see it
+                prev.next.store(node, Ordering::Release);
+                node
+            }
+        };
+        drop(guard);
+        table.buckets[bucket].store(node, Ordering::Release);
+        unsafe { &*node }
+    }
+
+    /// lock the dummy node in front of `so_key`, starting the search at `dummy`
+    /// everything between the returned dummy and `so_key` can't change until the guard is dropped
+    /// writers don't hold a read side critical section, so only nodes in the locked segment
+    /// are safe to look at
+    fn lock_segment<'a>(
+        &'a self,
+        mut dummy: &'a Node<K, V, L>,
+        so_key: u64,
+    ) -> (<L as Lock<'a>>::Guard, &'a Node<K, V, L>) {
+        'segment: loop {
+            let guard = dummy.lock();
+            let mut node = dummy.next();
+            while let Some(n) = node {
+                if n.so_key >= so_key {
+                    break;
+                }
+                if n.is_dummy() {
+                    // the bucket was split, dummy nodes are never freed so it is safe to unlock
+                    drop(guard);
+                    dummy = n;
+                    continue 'segment;
+                }
+                node = n.next();
+            }
+            return (guard, dummy);
+        }
+    }
+
+    /// find the nodes either side of where `so_key` goes, the first node returned is always
+    /// before so_key, the second is the first node at or after it
+    /// caller needs to hold the lock for start's segment
+    fn find_position(start: &Node<K, V, L>, so_key: u64) -> Position<'_, K, V, L> {
+        let mut prev = start;
+        let mut next = start.next();
+        while let Some(n) = next {
+            if n.so_key >= so_key {
+                break;
+            }
+            prev = n;
+            next = n.next();
+        }
+        (prev, next)
+    }
+
+    /// find the entry for `key`, returning it and the node before it, or if it isn't found the
+    /// node it should be inserted after
+    /// caller needs to hold the lock for start's segment
+    fn find_entry<'a, Q>(start: &'a Node<K, V, L>, so_key: u64, key: &Q) -> Position<'a, K, V, L>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        let (mut prev, mut node) = Self::find_position(start, so_key);
+        while let Some(n) = node {
+            if n.so_key != so_key {
+                break;
+            }
+            if let NodeKind::Entry(k, _) = &n.kind {
+                if k.borrow() == key {
+                    return (prev, Some(n));
+                }
+            }
+            prev = n;
+            node = n.next();
+        }
+        (prev, None)
+    }
+
+    /// lock the segment `hash` belongs to, initializing its bucket if needed
+    fn lock_for_write(&self, hash: u64) -> (<L as Lock<'_>>::Guard, &Node<K, V, L>) {
+        let table = self.table();
+        let bucket = self.bucket_for_write(table, hash as usize & (table.buckets.len() - 1));
+        self.lock_segment(bucket, so_regular(hash))
+    }
+
+    /// double the number of buckets if the map is too full
+    /// the new buckets are lazily initialized as they are written to, so this never touches
+    /// the list itself
+    fn maybe_resize(&self) {
+        if self.len() <= self.table().buckets.len() * MAX_LOAD {
+            return;
+        }
+        let guard = self.resize_lock.lock();
+        let old = self.table.load(Ordering::Acquire);
+        let old_buckets = unsafe { &(*old).buckets };
+        if self.len() > old_buckets.len() * MAX_LOAD {
+            let buckets: Box<[_]> = (0..old_buckets.len() * 2)
+                .map(|i| {
+                    AtomicPtr::new(
+                        old_buckets
+                            .get(i)
+                            .map_or(null_mut(), |b| b.load(Ordering::Acquire)),
+                    )
+                })
+                .collect();
+            let new = Box::into_raw(Box::new(Table { buckets, prev: old }));
+            //Ordering: new needs to be initialized before readers can see it
+            self.table.store(new, Ordering::Release);
+        }
+        drop(guard);
+    }
+
+    /// get the value for `key`, which stays valid for as long as the guard lives
+    pub fn get<'a, 'b, 'g, Q>(
+        &'a self,
+        guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'g>,
+        key: &Q,
+    ) -> Option<&'a V>
+    where
+        'b: 'g,
+        'g: 'a,
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.domain.check_guard(guard);
+        let hash = self.hasher.hash_one(key);
+        let so_key = so_regular(hash);
+        let mut node = self.bucket_for_read(hash).next();
+        while let Some(n) = node {
+            if n.so_key > so_key {
+                break;
+            }
+            if let NodeKind::Entry(k, v) = &n.kind {
+                if n.so_key == so_key && k.borrow() == key {
+                    return Some(v);
+                }
+            }
+            node = n.next();
+        }
+        None
+    }
+
+    /// whether `key` is in the map
+    pub fn contains_key<'a, 'b, 'g, Q>(
+        &'a self,
+        guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'g>,
+        key: &Q,
+    ) -> bool
+    where
+        'b: 'g,
+        'g: 'a,
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(guard, key).is_some()
+    }
+
+    /// iterate over every entry, in no particular order
+    pub fn iter<'a, 'b, 'g>(
+        &'a self,
+        guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'g>,
+    ) -> RcuHashMapIterator<'a, K, V, L>
+    where
+        'b: 'g,
+        'g: 'a,
+    {
+        self.domain.check_guard(guard);
+        RcuHashMapIterator {
+            next: unsafe { (*self.head).next() },
+        }
+    }
+
+    /// insert `key` if it isn't already in the map, otherwise hand key and value back
+    pub fn insert(&self, key: K, value: V) -> Result<(), (K, V)> {
+        let hash = self.hasher.hash_one(&key);
+        let so_key = so_regular(hash);
+        let (guard, segment) = self.lock_for_write(hash);
+        let (prev, found) = Self::find_entry(segment, so_key, &key);
+        if found.is_some() {
+            return Err((key, value));
+        }
+        let node = Box::into_raw(Box::new(Node {
+            so_key,
+            next: AtomicPtr::new(prev.next.load(Ordering::Relaxed)),
+            kind: NodeKind::Entry(key, value),
+        }));
+        //Ordering: node needs to be initialized before readers can see it
+        prev.next.store(node, Ordering::Release);
+        drop(guard);
+        self.len.fetch_add(1, Ordering::Relaxed);
+        self.maybe_resize();
+        Ok(())
+    }
+
+    /// insert `key`, replacing and returning the old value if there was one
+    /// readers always see either the old or the new value
+    ///
+    /// WARNING: if there was an old value this method calls `handle.quiescent_sync()` so it can
+    /// cause a deadlock
+    pub fn insert_or_replace(&self, key: K, value: V, handle: &mut R::Handle<'_>) -> Option<V> {
+        self.domain.check(handle.domain_id());
+        let hash = self.hasher.hash_one(&key);
+        let so_key = so_regular(hash);
+        let (guard, segment) = self.lock_for_write(hash);
+        let (prev, found) = Self::find_entry(segment, so_key, &key);
+        let next = match found {
+            Some(old) => old.next.load(Ordering::Relaxed),
+            None => prev.next.load(Ordering::Relaxed),
+        };
+        let node = Box::into_raw(Box::new(Node {
+            so_key,
+            next: AtomicPtr::new(next),
+            kind: NodeKind::Entry(key, value),
+        }));
+        //Ordering: node needs to be initialized before readers can see it
+        prev.next.store(node, Ordering::Release);
+        drop(guard);
+        match found {
+            Some(old) => {
+                handle.quiescent_sync();
+                Some(Self::into_value(old))
+            }
+            None => {
+                self.len.fetch_add(1, Ordering::Relaxed);
+                self.maybe_resize();
+                None
+            }
+        }
+    }
+
+    /// Safely remove `key` from the map
+    ///
+    /// WARNING: if key was found this method calls `handle.quiescent_sync()` so it can cause a
+    /// deadlock
+    pub fn remove<Q>(&self, key: &Q, handle: &mut R::Handle<'_>) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.domain.check(handle.domain_id());
+        let hash = self.hasher.hash_one(key);
+        let (guard, segment) = self.lock_for_write(hash);
+        let (prev, found) = Self::find_entry(segment, so_regular(hash), key);
+        let old = found?;
+        // old.next stays valid, so readers currently on old can keep going
+        prev.next
+            .store(old.next.load(Ordering::Relaxed), Ordering::Release);
+        drop(guard);
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        handle.quiescent_sync();
+        Some(Self::into_value(old))
+    }
+
+    /// take the value out of an entry that has been unlinked and synced
+    fn into_value(node: &Node<K, V, L>) -> V {
+        // Saftey: caller has made sure no one else can reference node
+        let node = unsafe { Box::from_raw(node as *const _ as *mut Node<K, V, L>) };
+        match node.kind {
+            NodeKind::Entry(_, v) => v,
+            NodeKind::Dummy(_) => unreachable!("dummy nodes are never removed"),
+        }
+    }
+}
+
+pub struct RcuHashMapIterator<'a, K, V, L> {
+    next: Option<&'a Node<K, V, L>>,
+}
+
+impl<'a, K, V, L> Iterator for RcuHashMapIterator<'a, K, V, L>
+where
+    L: for<'l> Lock<'l>,
+{
+    type Item = (&'a K, &'a V);
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(n) = self.next {
+            self.next = n.next();
+            if let NodeKind::Entry(k, v) = &n.kind {
+                return Some((k, v));
+            }
+        }
+        None
+    }
+}
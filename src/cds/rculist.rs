@@ -1,11 +1,9 @@
+use super::Domain;
 use crate::utils::Lock;
-use crate::{RcuHandle, RCU};
+use crate::{rcu_assign_pointer, rcu_dereference, AtomicPtr, RcuHandle, RCU};
 use std::cmp::{PartialEq, PartialOrd};
 use std::marker::PhantomData;
-use std::{
-    ptr::null_mut,
-    sync::atomic::{AtomicPtr, Ordering},
-};
+use std::{ptr::null_mut, sync::atomic::Ordering};
 
 #[derive(Debug)]
 pub struct RcuListElem<T>
@@ -48,8 +46,11 @@ where
     L: for<'a> Lock<'a>,
 {
     head: AtomicPtr<RcuListElem<T>>,
+    /// last element, for walking the list in reverse
+    tail: AtomicPtr<RcuListElem<T>>,
     // used for locking
     lock: L,
+    domain: Domain,
     _rcu: PhantomData<R>,
 }
 
@@ -70,9 +71,18 @@ where
             tmp = next;
         }
         self.head.store(null_mut(), Ordering::Relaxed);
+        self.tail.store(null_mut(), Ordering::Relaxed);
     }
 }
 
+/// iterates the list in order, from either end
+///
+/// elements that stay in the list for the whole walk are returned exactly once, elements
+/// inserted or removed during it may or may not be. Removed elements keep their links, so a walk
+/// that is on one carries on from where it was, and since links only ever point to smaller
+/// (prev) or larger (next) elements each end returns elements in order. Concurrent changes can
+/// make the two ends pass each other without meeting on the same element, so the walk ends once
+/// the next element from one end isn't strictly between the last ones returned from either end
 pub struct RcuListIterator<'a, T, R>
 where
     RcuListElem<T>: PartialEq,
@@ -83,6 +93,11 @@ where
 {
     _guard: PhantomData<R>,
     next: Option<&'a RcuListElem<T>>,
+    next_back: Option<&'a RcuListElem<T>>,
+    /// last element returned by next
+    front: Option<&'a T>,
+    /// last element returned by next_back
+    back: Option<&'a T>,
 }
 
 impl<'a, T, R> RcuListIterator<'a, T, R>
@@ -93,20 +108,34 @@ where
     T: PartialOrd,
     R: RCU + 'a,
 {
-    pub fn new<'b, L>(
-        _guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'b>,
+    pub fn new<'b, 'g, L>(
+        guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'g>,
         list: &'a RcuList<T, R, L>,
     ) -> Self
     where
-        'b: 'a,
+        'b: 'g,
+        'g: 'a,
         L: for<'c> Lock<'c>,
     {
-        let tmp = list.head.load(Ordering::Relaxed);
+        list.domain.check_guard(guard);
+        Self::from_ends(&list.head, &list.tail)
+    }
+
+    fn from_ends(head: &AtomicPtr<RcuListElem<T>>, tail: &AtomicPtr<RcuListElem<T>>) -> Self {
         Self {
-            next: unsafe { tmp.as_ref() },
+            next: unsafe { rcu_dereference(head).as_ref() },
+            next_back: unsafe { rcu_dereference(tail).as_ref() },
+            front: None,
+            back: None,
             _guard: PhantomData,
         }
     }
+
+    fn finish(&mut self) -> Option<&'a T> {
+        self.next = None;
+        self.next_back = None;
+        None
+    }
 }
 
 impl<'a, T, R> Iterator for RcuListIterator<'a, T, R>
@@ -119,11 +148,123 @@ where
 {
     type Item = &'a T;
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some(e) = self.next {
-            self.next = unsafe { e.next.load(Ordering::Relaxed).as_ref() };
-            return Some(&e.elem);
+        let e = self.next?;
+        if self.back.is_some_and(|back| compare(&e.elem, back).is_ge()) {
+            return self.finish();
         }
-        None
+        self.next = unsafe { rcu_dereference(&e.next).as_ref() };
+        self.front = Some(&e.elem);
+        Some(&e.elem)
+    }
+}
+
+impl<T, R> DoubleEndedIterator for RcuListIterator<'_, T, R>
+where
+    RcuListElem<T>: PartialEq,
+    RcuListElem<T>: PartialOrd,
+    T: PartialEq,
+    T: PartialOrd,
+    R: RCU,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let e = self.next_back?;
+        if self
+            .front
+            .is_some_and(|front| compare(&e.elem, front).is_le())
+        {
+            return self.finish();
+        }
+        self.next_back = unsafe { rcu_dereference(&e.prev).as_ref() };
+        self.back = Some(&e.elem);
+        Some(&e.elem)
+    }
+}
+
+/// a position in the list that can move in either direction, either on an element or on the
+/// "ghost" position between the tail and the head, like std's linked list cursors
+///
+/// the cursor follows the same links as RcuListIterator, so moving never returns an element out
+/// of order, and elements that stay in the list while the cursor is in use are never skipped.
+/// The current element may be removed while the cursor is on it, it stays valid until the end of
+/// the read side critical section and moving off it continues from where it was in the list
+pub struct Cursor<'a, T, R, L>
+where
+    RcuListElem<T>: PartialEq,
+    RcuListElem<T>: PartialOrd,
+    T: PartialEq,
+    T: PartialOrd,
+    R: RCU + 'a,
+    L: for<'c> Lock<'c>,
+{
+    list: &'a RcuList<T, R, L>,
+    /// None is the ghost position
+    current: Option<&'a RcuListElem<T>>,
+}
+
+impl<'a, T, R, L> Cursor<'a, T, R, L>
+where
+    RcuListElem<T>: PartialEq,
+    RcuListElem<T>: PartialOrd,
+    T: PartialEq,
+    T: PartialOrd,
+    R: RCU + 'a,
+    L: for<'c> Lock<'c>,
+{
+    /// the element the cursor is on, None on the ghost position
+    pub fn current(&self) -> Option<&'a T> {
+        self.current.map(|e| &e.elem)
+    }
+
+    /// the element move_next would move to
+    pub fn peek_next(&self) -> Option<&'a T> {
+        self.next_elem().map(|e| &e.elem)
+    }
+
+    /// the element move_prev would move to
+    pub fn peek_prev(&self) -> Option<&'a T> {
+        self.prev_elem().map(|e| &e.elem)
+    }
+
+    /// move to the next element, from the last element to the ghost position, and from the
+    /// ghost position to the head
+    pub fn move_next(&mut self) {
+        self.current = self.next_elem();
+    }
+
+    /// move to the previous element, from the head to the ghost position, and from the ghost
+    /// position to the tail
+    pub fn move_prev(&mut self) {
+        self.current = self.prev_elem();
+    }
+
+    /// move to the first element that isn't less than `key`, or the ghost position if there
+    /// isn't one. Walks from the current position in whichever direction is needed
+    pub fn seek(&mut self, key: &T) {
+        if self.current.is_none() {
+            self.current = unsafe { rcu_dereference(&self.list.head).as_ref() };
+        }
+        while let Some(prev) = self.prev_elem().filter(|p| compare(&p.elem, key).is_ge()) {
+            self.current = Some(prev);
+        }
+        while let Some(e) = self.current.filter(|e| e.elem < *key) {
+            self.current = unsafe { rcu_dereference(&e.next).as_ref() };
+        }
+    }
+
+    fn next_elem(&self) -> Option<&'a RcuListElem<T>> {
+        let next = match self.current {
+            Some(e) => &e.next,
+            None => &self.list.head,
+        };
+        unsafe { rcu_dereference(next).as_ref() }
+    }
+
+    fn prev_elem(&self) -> Option<&'a RcuListElem<T>> {
+        let prev = match self.current {
+            Some(e) => &e.prev,
+            None => &self.list.tail,
+        };
+        unsafe { rcu_dereference(prev).as_ref() }
     }
 }
 
@@ -153,56 +294,309 @@ where
     pub fn new() -> Self {
         Self {
             head: AtomicPtr::new(null_mut()),
+            tail: AtomicPtr::new(null_mut()),
             lock: L::new(),
+            domain: Domain::new(),
             _rcu: PhantomData,
         }
     }
 
-    fn lock(&self) -> <L as Lock>::Guard {
+    /// create a list protected by `rcu`, iterating it with a guard or removing with a handle
+    /// from any other instance panics, even in release builds. Lists made with new are bound to
+    /// the first instance they are used with, and only checked in debug builds
+    pub fn new_in(rcu: &R) -> Self {
+        Self {
+            head: AtomicPtr::new(null_mut()),
+            tail: AtomicPtr::new(null_mut()),
+            lock: L::new(),
+            domain: Domain::bound(rcu.domain_id()),
+            _rcu: PhantomData,
+        }
+    }
+
+    /// a cursor on the first element, or the ghost position if the list is empty
+    pub fn cursor_front<'a, 'b, 'g>(
+        &'a self,
+        guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'g>,
+    ) -> Cursor<'a, T, R, L>
+    where
+        'b: 'g,
+        'g: 'a,
+    {
+        self.domain.check_guard(guard);
+        Cursor {
+            list: self,
+            current: unsafe { rcu_dereference(&self.head).as_ref() },
+        }
+    }
+
+    /// a cursor on the last element, or the ghost position if the list is empty
+    pub fn cursor_back<'a, 'b, 'g>(
+        &'a self,
+        guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'g>,
+    ) -> Cursor<'a, T, R, L>
+    where
+        'b: 'g,
+        'g: 'a,
+    {
+        self.domain.check_guard(guard);
+        Cursor {
+            list: self,
+            current: unsafe { rcu_dereference(&self.tail).as_ref() },
+        }
+    }
+
+    /// find an element using the list's order, `pred` compares an element to what is being
+    /// looked for, like slice::binary_search_by. The walk stops at the first element that
+    /// compares Equal, or Greater since every element after it is greater too
+    pub fn find<'a, 'b, 'g, F>(
+        &'a self,
+        guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'g>,
+        mut pred: F,
+    ) -> Option<&'a T>
+    where
+        'b: 'g,
+        'g: 'a,
+        F: FnMut(&T) -> std::cmp::Ordering,
+    {
+        for e in RcuListIterator::new(guard, self) {
+            match pred(e) {
+                std::cmp::Ordering::Less => continue,
+                std::cmp::Ordering::Equal => return Some(e),
+                std::cmp::Ordering::Greater => return None,
+            }
+        }
+        None
+    }
+
+    /// the element equal to `key`, stops at the first element greater than it
+    pub fn get<'a, 'b, 'g>(
+        &'a self,
+        guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'g>,
+        key: &T,
+    ) -> Option<&'a T>
+    where
+        'b: 'g,
+        'g: 'a,
+    {
+        self.find(guard, |e| compare(e, key))
+    }
+
+    /// whether an element equal to `key` is in the list
+    pub fn contains<'a, 'b, 'g>(
+        &'a self,
+        guard: &'a <<R as RCU>::Handle<'b> as RcuHandle<'b>>::Guard<'g>,
+        key: &T,
+    ) -> bool
+    where
+        'b: 'g,
+        'g: 'a,
+    {
+        self.get(guard, key).is_some()
+    }
+
+    fn lock(&self) -> <L as Lock<'_>>::Guard {
         self.lock.lock()
     }
 
+    /// in debug builds, panic if the list lock isn't held, for the writer side helpers
+    fn check_locked(&self) {
+        debug_assert!(self.lock.is_locked(), "rcu list lock must be held");
+    }
+
+    /// iterate over the list without a read side critical section
+    ///
+    /// # Safety
+    ///
+    /// Need to ensure no elements are removed and dropped while the iterator is alive, e.g. by
+    /// holding a lock every remover also takes
+    pub(crate) unsafe fn iter_unguarded(&self) -> RcuListIterator<'_, T, R> {
+        RcuListIterator::from_ends(&self.head, &self.tail)
+    }
+
     pub fn insert(&self, elem: T) -> &T {
-        let new_elem = RcuListElem {
-            next: AtomicPtr::new(null_mut()),
-            prev: AtomicPtr::new(null_mut()),
-            elem,
-        };
-        //TODO UNOPTIMIZED create new_elem on the heap directly, instead of copying from stack
-        let new_elem: *mut RcuListElem<T> = Box::leak(Box::new(new_elem));
+        let guard = self.lock();
+        let (prev, next) = self.find_position(&elem);
+        let inserted = unsafe { self.link(elem, prev, next) };
+        drop(guard);
+        inserted
+    }
 
+    /// insert elem, unless an equal element is already in the list, in which case it is handed
+    /// back
+    pub fn try_insert(&self, elem: T) -> Result<&T, T> {
         let guard = self.lock();
-        let mut prev = self.head.load(Ordering::Relaxed);
+        let (prev, next) = self.find_position(&elem);
+        if !next.is_null() && unsafe { (*next).elem == elem } {
+            return Err(elem);
+        }
+        let inserted = unsafe { self.link(elem, prev, next) };
+        drop(guard);
+        Ok(inserted)
+    }
+
+    /// find the elements either side of where elem belongs, prev is null if elem belongs at the
+    /// head of the list, next is the first element >= elem, or null
+    /// caller must hold the list lock
+    fn find_position(&self, elem: &T) -> (*mut RcuListElem<T>, *mut RcuListElem<T>) {
+        self.check_locked();
+        let mut prev = null_mut();
+        let mut next = self.head.load(Ordering::Relaxed);
+        while !next.is_null() && unsafe { (*next).elem < *elem } {
+            prev = next;
+            next = unsafe { (*next).next.load(Ordering::Relaxed) };
+        }
+        (prev, next)
+    }
+
+    /// # Safety
+    ///
+    /// caller must hold the list lock, and prev and next must be adjacent apart from elements
+    /// being unlinked, e.g. from find_position
+    unsafe fn link(&self, elem: T, prev: *mut RcuListElem<T>, next: *mut RcuListElem<T>) -> &T {
+        self.check_locked();
+        //TODO UNOPTIMIZED create new_elem on the heap directly, instead of copying from stack
+        let new_elem: *mut RcuListElem<T> = Box::leak(Box::new(RcuListElem {
+            next: AtomicPtr::new(next),
+            prev: AtomicPtr::new(prev),
+            elem,
+        }));
+        // new_elem is only initialized above, readers must not be able to reach it before then
         if prev.is_null() {
-            self.head.store(new_elem, Ordering::Relaxed);
+            rcu_assign_pointer(&self.head, new_elem);
+        } else {
+            rcu_assign_pointer(unsafe { &(*prev).next }, new_elem);
+        }
+        if next.is_null() {
+            rcu_assign_pointer(&self.tail, new_elem);
         } else {
+            rcu_assign_pointer(unsafe { &(*next).prev }, new_elem);
+        }
+        unsafe { &(*new_elem).elem }
+    }
+
+    /// checks every element is strictly greater than the one before it, and that the prev
+    /// pointers and tail match, i.e. the list is sorted with no duplicates
+    /// takes the list lock, intended for debug assertions
+    pub fn is_strictly_ordered(&self) -> bool {
+        let guard = self.lock();
+        let mut prev: *mut RcuListElem<T> = null_mut();
+        let mut e = self.head.load(Ordering::Relaxed);
+        while !e.is_null() {
             unsafe {
-                let mut next = (*prev).next.load(Ordering::Relaxed);
-                while !next.is_null() && (*next < *new_elem) {
-                    prev = next;
-                    next = (*next).next.load(Ordering::Relaxed);
+                if (*e).prev.load(Ordering::Relaxed) != prev {
+                    return false;
                 }
-                (*new_elem).next.store(next, Ordering::Relaxed);
-                (*new_elem).prev.store(prev, Ordering::Relaxed);
-                (*prev).next.store(new_elem, Ordering::Relaxed);
-                if !next.is_null() {
-                    (*next).prev.store(new_elem, Ordering::Relaxed);
+                if !prev.is_null() && *prev >= *e {
+                    return false;
                 }
+                prev = e;
+                e = (*e).next.load(Ordering::Relaxed);
             }
         }
+        if self.tail.load(Ordering::Relaxed) != prev {
+            return false;
+        }
         drop(guard);
+        true
+    }
 
-        unsafe { &(*new_elem).elem }
+    /// Safely remove an element from the list, returns None if it isn't in the list, e.g.
+    /// because another thread removed it first, in which case there is nothing to sync for
+    ///
+    /// WARNING: since this method calls `handle.sync()` it can cause a deadlock
+    pub fn remove(&self, elem: &T, handle: &mut R::Handle<'_>) -> Option<T> {
+        self.domain.check(handle.domain_id());
+        let popped_elem = unsafe { self.remove_unsynced(elem) }?;
+        handle.quiescent_sync();
+
+        Some(unsafe { Box::from_raw(popped_elem) }.elem)
     }
 
-    /// Safely remove an element from the list
+    /// swap `new` into the position of the element equal to `old`, and return the old element
+    /// once no reader can reference it anymore. Readers always see either the old or the new
+    /// element, unlike removing and inserting again. Hands new back if old isn't in the list,
+    /// e.g. because another thread removed it first, in which case there is nothing to sync for
+    ///
+    /// panics if new doesn't sort into the same position
     ///
     /// WARNING: since this method calls `handle.sync()` it can cause a deadlock
-    pub fn remove(&self, elem: &T, handle: &mut R::Handle<'_>) -> T {
-        let popped_elem = unsafe { self.remove_unsynced(elem) };
+    pub fn replace(&self, old: &T, new: T, handle: &mut R::Handle<'_>) -> Result<T, T> {
+        self.domain.check(handle.domain_id());
+        let guard = self.lock();
+        let e = self.find_locked(|e| compare(e, old));
+        if e.is_null() {
+            return Err(new);
+        }
+        let next = unsafe { (*e).next.load(Ordering::Relaxed) };
+        let prev = unsafe { (*e).prev.load(Ordering::Relaxed) };
+        assert!(
+            (prev.is_null() || unsafe { (*prev).elem < new })
+                && (next.is_null() || unsafe { new < (*next).elem }),
+            "replacement must sort into the same position as the element it replaces"
+        );
+        // linking between e's neighbours unlinks e, whose own links are left alone so readers
+        // currently on it can keep going
+        unsafe { self.link(new, prev, next) };
+        drop(guard);
         handle.quiescent_sync();
 
-        unsafe { Box::from_raw(popped_elem) }.elem
+        Ok(unsafe { Box::from_raw(e) }.elem)
+    }
+
+    /// remove the element whose key, as given by `f`, equals `key`. Keys must be in the same
+    /// order as the list, like slice::binary_search_by_key, so the search stops at the first
+    /// greater key
+    ///
+    /// WARNING: since this method calls `handle.sync()` it can cause a deadlock
+    pub fn remove_by_key<K, F>(&self, key: &K, mut f: F, handle: &mut R::Handle<'_>) -> Option<T>
+    where
+        K: PartialOrd,
+        F: FnMut(&T) -> K,
+    {
+        self.domain.check(handle.domain_id());
+        let guard = self.lock();
+        let e = self.find_locked(|e| compare(&f(e), key));
+        if e.is_null() {
+            return None;
+        }
+        unsafe { self.unlink(e) };
+        drop(guard);
+        handle.quiescent_sync();
+
+        Some(unsafe { Box::from_raw(e) }.elem)
+    }
+
+    /// remove every element `pred` returns true for, syncing once for all of them
+    ///
+    /// WARNING: since this method calls `handle.sync()` it can cause a deadlock
+    pub fn remove_if<F>(&self, mut pred: F, handle: &mut R::Handle<'_>) -> Vec<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        self.domain.check(handle.domain_id());
+        let guard = self.lock();
+        let mut removed = Vec::new();
+        let mut e = self.head.load(Ordering::Relaxed);
+        while !e.is_null() {
+            // unlinking leaves e.next alone, so the walk can carry on from it
+            let next = unsafe { (*e).next.load(Ordering::Relaxed) };
+            if pred(unsafe { &(*e).elem }) {
+                unsafe { self.unlink(e) };
+                removed.push(e);
+            }
+            e = next;
+        }
+        drop(guard);
+        if removed.is_empty() {
+            return Vec::new();
+        }
+        handle.quiescent_sync();
+
+        removed
+            .into_iter()
+            .map(|e| unsafe { Box::from_raw(e) }.elem)
+            .collect()
     }
 
     /// # Safety
@@ -210,30 +604,70 @@ where
     /// Need to ensure no other threads are referencing the given Tentry before it is
     /// dropped, this can be done by syncing, plus waiting for all other threads already syncing
     /// to finish.
-    pub unsafe fn remove_unsynced(&self, elem: &T) -> *mut RcuListElem<T> {
+    ///
+    /// returns None if elem isn't in the list
+    pub unsafe fn remove_unsynced(&self, elem: &T) -> Option<*mut RcuListElem<T>> {
         let guard = self.lock();
+        let e = self.find_locked(|e| compare(e, elem));
+        if e.is_null() {
+            return None;
+        }
+        unsafe { self.unlink(e) };
+        drop(guard);
+        Some(e)
+    }
+
+    /// the first element `pred` compares Equal, or null if it reaches one that compares Greater
+    /// or the end of the list first
+    /// caller must hold the list lock
+    fn find_locked<F>(&self, mut pred: F) -> *mut RcuListElem<T>
+    where
+        F: FnMut(&T) -> std::cmp::Ordering,
+    {
+        self.check_locked();
         let mut e = self.head.load(Ordering::Relaxed);
-        while !e.is_null() && unsafe { (*e).elem != *elem } {
-            //TODO FIXME how are we hitting the expect condition
-            e = unsafe { (*e).next.load(Ordering::Relaxed) };
-        }
-        assert!(!e.is_null());
-        //need to update container struct if head changes
-        //TODO FIXME find &self in list
-        //remove e from list, e.g. make e.prev <---> e.next
+        while !e.is_null() {
+            match pred(unsafe { &(*e).elem }) {
+                std::cmp::Ordering::Less => e = unsafe { (*e).next.load(Ordering::Relaxed) },
+                std::cmp::Ordering::Equal => return e,
+                std::cmp::Ordering::Greater => break,
+            }
+        }
+        null_mut()
+    }
+
+    /// remove e from the list, e.g. make e.prev <---> e.next, e's own links are left alone so
+    /// readers currently on it can keep going
+    ///
+    /// # Safety
+    ///
+    /// caller must hold the list lock, and e must be in the list
+    unsafe fn unlink(&self, e: *mut RcuListElem<T>) {
+        self.check_locked();
         let next = unsafe { (*e).next.load(Ordering::Relaxed) };
         let prev = unsafe { (*e).prev.load(Ordering::Relaxed) };
-        if !next.is_null() {
-            unsafe { (*next).prev.store(prev, Ordering::Relaxed) };
+        // readers that reach next or prev through the new links need to see them initialized too
+        if next.is_null() {
+            rcu_assign_pointer(&self.tail, prev);
+        } else {
+            rcu_assign_pointer(unsafe { &(*next).prev }, prev);
         }
         if !prev.is_null() {
-            unsafe { (*prev).next.store(next, Ordering::Relaxed) };
+            rcu_assign_pointer(unsafe { &(*prev).next }, next);
         } else {
             // if elem is self.head update the ptr
-            self.head.store(next, Ordering::Relaxed);
+            rcu_assign_pointer(&self.head, next);
         }
+    }
+}
 
-        drop(guard);
-        e
+/// compare two partially ordered values, treating anything that isn't less or equal as greater
+fn compare<T: PartialOrd>(a: &T, b: &T) -> std::cmp::Ordering {
+    if a < b {
+        std::cmp::Ordering::Less
+    } else if a == b {
+        std::cmp::Ordering::Equal
+    } else {
+        std::cmp::Ordering::Greater
     }
 }
@@ -1,16 +1,59 @@
 #![deny(unsafe_op_in_unsafe_fn)]
+pub mod async_rcu;
+pub mod bp;
 pub mod cds;
+pub mod mb;
+pub mod memb;
 pub mod qsbr;
+pub mod srcu;
+pub mod tree;
 pub mod utils;
 
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicPtr;
+#[cfg(not(loom))]
+pub(crate) use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// next id handed out by new_domain_id
+static NEXT_DOMAIN_ID: AtomicU64 = AtomicU64::new(1);
+
+/// a unique id for a new rcu instance, 0 is never handed out
+pub(crate) fn new_domain_id() -> u64 {
+    NEXT_DOMAIN_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 pub trait RCU {
     type Handle<'a>: RcuHandle<'a>
     where
         Self: 'a;
     fn new() -> Self;
-    fn register(&self, id: u64) -> Self::Handle<'_>;
+    fn register(&self, id: u64) -> Result<Self::Handle<'_>, RegisterError>;
+    /// unique id of this instance, handles and guards carry it so they can be checked against
+    /// the instance a structure is protected by
+    fn domain_id(&self) -> u64;
+}
+
+/// returned by RCU::register when the handle can't be registered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// a handle with the same id is already registered
+    DuplicateId(u64),
+    /// the rcu instance can't register any more handles
+    Full,
+}
+
+impl std::fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DuplicateId(id) => write!(f, "a handle with id {} is already registered", id),
+            Self::Full => write!(f, "no more handles can be registered"),
+        }
+    }
 }
 
+impl std::error::Error for RegisterError {}
+
 pub trait RcuHandle<'a> {
     type Guard<'g>: RcuGuard<'g>
     where
@@ -20,14 +63,53 @@ pub trait RcuHandle<'a> {
     where
         Self: 'a,
         'a: 's;
-    fn read(&self) -> Self::Guard<'a>;
+    fn read(&self) -> Self::Guard<'_>;
     fn quiescent_state(&mut self);
     fn sleep(self) -> Self::Sleeper<'a>;
     fn sync(&self);
     fn quiescent_sync(&mut self);
+    /// true while a guard from this handle is alive, i.e. rcu_read_lock_held
+    fn is_reading(&self) -> bool;
+    /// see RCU::domain_id
+    fn domain_id(&self) -> u64;
+}
+
+pub trait RcuGuard<'a> {
+    /// id of the rcu instance this guard's read side critical section belongs to, see
+    /// RCU::domain_id
+    fn domain_id(&self) -> u64;
+}
+
+/// panic if `guard` isn't from `rcu`, for structures protected by a single rcu instance to
+/// check the guards they are given
+pub fn check_guard<'g, R, G>(rcu: &R, guard: &G)
+where
+    R: RCU,
+    G: RcuGuard<'g>,
+{
+    assert_eq!(
+        guard.domain_id(),
+        rcu.domain_id(),
+        "rcu guard is from a different rcu instance"
+    );
+}
+
+/// publish `new` through `p`, like Linux's rcu_assign_pointer. Everything written to `*new`
+/// before this happens before any reader that loads it with rcu_dereference accesses it, so
+/// readers never see a half initialized object
+pub fn rcu_assign_pointer<T>(p: &AtomicPtr<T>, new: *mut T) {
+    //Ordering: pairs with the Acquire in rcu_dereference
+    p.store(new, Ordering::Release);
 }
 
-pub trait RcuGuard<'a> {}
+/// load a pointer published with rcu_assign_pointer, like Linux's rcu_dereference. The
+/// pointee is fully initialized, but is only guaranteed to stay alive until the end of the
+/// current read side critical section
+pub fn rcu_dereference<T>(p: &AtomicPtr<T>) -> *mut T {
+    //Ordering: Acquire since rust has no consume ordering, pairs with the Release in
+    //rcu_assign_pointer
+    p.load(Ordering::Acquire)
+}
 
 pub trait SleepingRcu<'a> {
     type Handle: RcuHandle<'a>;
@@ -0,0 +1,266 @@
+use crate::cds::rculist::*;
+use crate::utils::{self, Lock, ReaderEntry, Sleeper};
+use crate::{new_domain_id, RcuGuard, RcuHandle, RegisterError, RCU};
+use std::marker::PhantomData;
+use std::sync::atomic::{self, AtomicU32, Ordering};
+
+/// low bits of a reader's ctr count how deeply nested its read side critical sections are
+const NEST_MASK: u32 = (1 << 31) - 1;
+/// high bit of a reader's ctr is the grace period phase it started reading in
+const GP_PHASE: u32 = 1 << 31;
+
+/// Mb memory barrier based rcu
+/// readers mark themselves active in read() and inactive when the guard is dropped, so unlike
+/// Qsbr threads never need to call quiescent_state, at the cost of a full fence on every read
+#[derive(Debug)]
+pub struct Mb<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    threads: RcuList<MbEntry, Self, L>,
+    /// phase readers copy into their ctr when starting a read side critical section
+    gp_ctr: AtomicU32,
+    /// 1 while a sync is waiting on readers, readers wake it when leaving a critical section
+    gp_futex: AtomicU32,
+    /// serializes syncs, and unregistering threads so a sync never sees a freed MbEntry
+    lock: L,
+    /// readers only need compiler barriers, since sync uses membarrier to force a full barrier
+    /// on every running thread, see crate::memb
+    membarrier: bool,
+    /// see RCU::domain_id
+    domain_id: u64,
+}
+
+impl<L> Default for Mb<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L> RCU for Mb<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    type Handle<'a> = MbThreadHandle<'a, L> where L: 'a;
+    /// create a new Mb
+    fn new() -> Self {
+        Self::with_membarrier(false)
+    }
+    /// register a new thread with Mb
+    /// takes an unique id for this handle, fails if id is already registered
+    fn register(&self, id: u64) -> Result<Self::Handle<'_>, RegisterError> {
+        let elem: &MbEntry = self
+            .threads
+            .try_insert(MbEntry::new(id, AtomicU32::new(0)))
+            .map_err(|_| RegisterError::DuplicateId(id))?;
+        debug_assert!(self.threads.is_strictly_ordered());
+        Ok(MbThreadHandle {
+            info: elem,
+            mb: self,
+        })
+    }
+    fn domain_id(&self) -> u64 {
+        self.domain_id
+    }
+}
+
+impl<L> Mb<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    pub(crate) fn with_membarrier(membarrier: bool) -> Self {
+        Self {
+            threads: RcuList::new(),
+            gp_ctr: AtomicU32::new(0),
+            gp_futex: AtomicU32::new(0),
+            lock: L::new(),
+            membarrier,
+            domain_id: new_domain_id(),
+        }
+    }
+
+    /// writer side full barrier, pairs with smp_mb_slave
+    fn smp_mb_master(&self) {
+        if self.membarrier {
+            crate::memb::membarrier();
+        } else {
+            atomic::fence(Ordering::SeqCst);
+        }
+    }
+
+    /// reader side barrier, only needs to stop the compiler from reordering when sync uses
+    /// membarrier
+    fn smp_mb_slave(&self) {
+        if self.membarrier {
+            atomic::compiler_fence(Ordering::SeqCst);
+        } else {
+            atomic::fence(Ordering::SeqCst);
+        }
+    }
+
+    /// flip the grace period phase, then wait for every reader still in the old phase to leave
+    /// its critical section, see utils::flip_and_wait
+    /// caller must hold self.lock
+    fn flip_and_wait(&self) {
+        let flip = || {
+            let phase = self.gp_ctr.load(Ordering::Relaxed) ^ GP_PHASE;
+            self.gp_ctr.store(phase, Ordering::Relaxed);
+            phase
+        };
+        let in_old_phase = |ctr: &AtomicU32, phase: &u32| {
+            let ctr = ctr.load(Ordering::Relaxed);
+            ctr & NEST_MASK != 0 && (ctr ^ phase) & GP_PHASE != 0
+        };
+        // Saftey: self.lock is held, so no MbEntry can be removed
+        unsafe {
+            utils::flip_and_wait(
+                &self.threads,
+                &self.gp_futex,
+                || self.smp_mb_master(),
+                flip,
+                in_old_phase,
+            )
+        };
+    }
+}
+
+///created via Mb::register(), used to register a thread with the Mb
+pub struct MbThreadHandle<'a, L>
+where
+    L: for<'b> Lock<'b>,
+    Self: 'a,
+{
+    mb: &'a Mb<L>,
+    info: &'a MbEntry,
+}
+
+impl<'a, L> RcuHandle<'a> for MbThreadHandle<'a, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    type Guard<'b> = MbGuard<'b, L> where Self: 'a, 'a: 'b;
+    type Sleeper<'b> = MbSleeper<'b, L> where Self: 'a, 'a: 'b;
+    /// readers are only active inside a critical section, so sleeping is a no op
+    fn sleep(self) -> Self::Sleeper<'a> {
+        Self::Sleeper { handle: self }
+    }
+    /// read starts an rcu critical section, which lasts until the returned MbGuard is dropped
+    /// critical sections can be nested, only the outer most one is tracked by sync
+    fn read(&self) -> Self::Guard<'_> {
+        // the handle is Sync, so other threads sharing it may be updating ctr too
+        let mut ctr = self.info.ctr.load(Ordering::Relaxed);
+        loop {
+            let new = if ctr & NEST_MASK == 0 {
+                self.mb.gp_ctr.load(Ordering::Relaxed) | 1
+            } else {
+                ctr + 1
+            };
+            match self.info.ctr.compare_exchange_weak(
+                ctr,
+                new,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => ctr = current,
+            }
+        }
+        if ctr & NEST_MASK == 0 {
+            //Ordering: ctr needs to be visible before any reads in the critical section
+            self.mb.smp_mb_slave();
+        }
+        MbGuard {
+            info: self.info,
+            mb: self.mb,
+            _not_send: PhantomData,
+        }
+    }
+    /// readers track their own critical sections, so this is a no op
+    fn quiescent_state(&mut self) {}
+
+    fn quiescent_sync(&mut self) {
+        self.sync();
+    }
+
+    fn is_reading(&self) -> bool {
+        self.info.ctr.load(Ordering::Relaxed) & NEST_MASK != 0
+    }
+
+    fn domain_id(&self) -> u64 {
+        self.mb.domain_id
+    }
+
+    /// Used to synchronize all MbThreadHandles, blocks until every read side critical section
+    /// that started before sync was called has ended
+    /// calling sync inside a critical section deadlocks
+    fn sync(&self) {
+        let guard = self.mb.lock.lock();
+        //Ordering: removals before sync need to be visible before checking readers
+        self.mb.smp_mb_master();
+        // two flips are needed, a reader could have loaded the old phase just before the first
+        // flip but not stored it to ctr until after we checked it
+        self.mb.flip_and_wait();
+        self.mb.flip_and_wait();
+        //Ordering: make sure all the readers finished before returning
+        self.mb.smp_mb_master();
+        drop(guard);
+    }
+}
+
+//unregistering a thread
+impl<L> Drop for MbThreadHandle<'_, L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// unregisters the given handle with Mb
+    fn drop(&mut self) {
+        utils::unregister(&self.mb.threads, &self.mb.lock, self.info);
+    }
+}
+
+pub type MbSleeper<'a, L> = Sleeper<MbThreadHandle<'a, L>>;
+
+/// MbGuard, marks its thread as reading until it is dropped
+pub struct MbGuard<'a, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    info: &'a MbEntry,
+    mb: &'a Mb<L>,
+    // a critical section ends on the thread that started it, whose reads the barriers order
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<L> RcuGuard<'_> for MbGuard<'_, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    fn domain_id(&self) -> u64 {
+        self.mb.domain_id
+    }
+}
+
+// end the rcu critical section
+impl<L> Drop for MbGuard<'_, L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// ends the critical section
+    fn drop(&mut self) {
+        //Ordering: reads in the critical section need to happen before ctr is decremented
+        self.mb.smp_mb_slave();
+        let ctr = self.info.ctr.fetch_sub(1, Ordering::Relaxed);
+        if ctr & NEST_MASK == 1 {
+            //Ordering: either sync sees us done, or we see it waiting
+            self.mb.smp_mb_slave();
+            utils::wake_syncer(&self.mb.gp_futex);
+        }
+    }
+}
+
+/// GP_PHASE bit of ctr is the phase this thread started reading in, the rest is how deeply
+/// nested its read side critical sections are, 0 means not reading
+type MbEntry = ReaderEntry<AtomicU32>;
@@ -0,0 +1,112 @@
+use crate::mb::{Mb, MbThreadHandle};
+use crate::utils::Lock;
+use crate::{RegisterError, RCU};
+use std::sync::atomic::{self, Ordering};
+use std::sync::OnceLock;
+
+#[cfg(target_os = "linux")]
+const MEMBARRIER_CMD_QUERY: libc::c_int = 0;
+#[cfg(target_os = "linux")]
+const MEMBARRIER_CMD_PRIVATE_EXPEDITED: libc::c_int = 1 << 3;
+#[cfg(target_os = "linux")]
+const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: libc::c_int = 1 << 4;
+
+/// Memb membarrier accelerated rcu
+/// same as Mb, except readers only use compiler barriers, and sync uses the membarrier syscall
+/// to force a full memory barrier on every running thread instead. Makes reads much cheaper
+/// and syncs more expensive. Falls back to behaving exactly like Mb if membarrier isn't
+/// supported by the OS
+#[derive(Debug)]
+pub struct Memb<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    mb: Mb<L>,
+}
+
+impl<L> Default for Memb<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L> Memb<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// true if sync is using membarrier, false if it fell back to Mb
+    pub fn has_membarrier(&self) -> bool {
+        membarrier_supported()
+    }
+}
+
+impl<L> RCU for Memb<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    type Handle<'a> = MbThreadHandle<'a, L> where L: 'a;
+    /// create a new Memb, registering the process for expedited membarriers if this is the first
+    fn new() -> Self {
+        Self {
+            mb: Mb::with_membarrier(membarrier_supported()),
+        }
+    }
+    /// register a new thread with Memb
+    /// takes an unique id for this handle, fails if id is already registered
+    fn register(&self, id: u64) -> Result<Self::Handle<'_>, RegisterError> {
+        self.mb.register(id)
+    }
+    fn domain_id(&self) -> u64 {
+        self.mb.domain_id()
+    }
+}
+
+/// checks if MEMBARRIER_CMD_PRIVATE_EXPEDITED is available, and registers the process to use it
+/// only does the syscalls the first time it is called
+pub(crate) fn membarrier_supported() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(register_membarrier)
+}
+
+#[cfg(target_os = "linux")]
+fn register_membarrier() -> bool {
+    // Saftey: membarrier doesn't touch memory, worst case it returns an error
+    let cmds = unsafe { libc::syscall(libc::SYS_membarrier, MEMBARRIER_CMD_QUERY, 0, 0) };
+    if cmds < 0 || cmds & MEMBARRIER_CMD_PRIVATE_EXPEDITED as libc::c_long == 0 {
+        return false;
+    }
+    unsafe {
+        libc::syscall(
+            libc::SYS_membarrier,
+            MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED,
+            0,
+            0,
+        ) == 0
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn register_membarrier() -> bool {
+    false
+}
+
+/// issue a full memory barrier on every running thread in this process
+/// falls back to a fence on this thread if the syscall fails, which is only correct if readers
+/// are using full fences as well
+pub(crate) fn membarrier() {
+    #[cfg(target_os = "linux")]
+    {
+        // Saftey: membarrier doesn't touch memory, worst case it returns an error
+        let ret =
+            unsafe { libc::syscall(libc::SYS_membarrier, MEMBARRIER_CMD_PRIVATE_EXPEDITED, 0, 0) };
+        if ret == 0 {
+            return;
+        }
+        // only registered processes are allowed to use membarrier, so this is a bug
+        debug_assert!(false, "membarrier failed after registering");
+    }
+    atomic::fence(Ordering::SeqCst);
+}
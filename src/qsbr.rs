@@ -1,19 +1,58 @@
 use crate::cds::rculist::*;
-use crate::utils::Lock;
-use crate::{RcuGuard, RcuHandle, SleepingRcu, RCU};
+use crate::utils::{gp_target, seq_reached, Lock};
+use crate::{new_domain_id, RcuGuard, RcuHandle, RegisterError, SleepingRcu, RCU};
+use std::collections::VecDeque;
+use std::fmt;
 use std::marker::PhantomData;
-use std::sync::atomic::{self, AtomicU32, Ordering};
+use std::ptr;
+use std::sync::atomic::{self, AtomicBool, AtomicPtr, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 /// QSBR quiescent state based reclamation
 /// This is the main entry point to everything
 #[derive(Debug)]
 pub struct Qsbr<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    shared: Arc<QsbrShared<L>>,
+    /// see RCU::domain_id
+    domain_id: u64,
+    /// nanoseconds a sync waits before warning about stalled threads, 0 if disabled
+    stall_timeout: AtomicU64,
+    /// grace period sequence number, odd while a sync is waiting on readers, bumped twice per
+    /// grace period. Concurrent syncs share grace periods through it, see QsbrThreadHandle::sync
+    gp_seq: AtomicU32,
+    /// WaitStrategy used by sync, see WaitStrategy::encode
+    wait_strategy: AtomicU64,
+    /// background thread freeing values handed off with defer_free, if started
+    reclaimer: Mutex<Option<JoinHandle<()>>>,
+}
+
+/// the parts of Qsbr the reclaimer thread needs, behind an Arc so Qsbr can still be moved
+/// while the reclaimer is running
+#[derive(Debug)]
+struct QsbrShared<L>
 where
     L: for<'a> Lock<'a>,
 {
     //threads will leave as long as self does
-    threads: RcuList<Tentry, Self, L>,
+    threads: RcuList<Tentry, Qsbr<L>, L>,
     lock: L,
+    deferred: DeferredQueue,
+}
+
+impl<L> Drop for Qsbr<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// stops the reclaimer, values it hasn't freed yet are dropped along with the queue, which
+    /// is safe since no handles are left
+    fn drop(&mut self) {
+        self.stop_reclaimer();
+    }
 }
 
 impl<L> Default for Qsbr<L>
@@ -29,16 +68,342 @@ impl<L> Qsbr<L>
 where
     L: for<'a> Lock<'a>,
 {
+    /// enable the stall watchdog, syncs that wait longer than `timeout` for a grace period log a
+    /// warning naming each thread that hasn't passed through a quiescent state, and repeat it
+    /// every `timeout` until the grace period completes. `None` disables it
+    ///
+    /// while enabled syncs poll instead of sleeping on a futex, so they are a bit slower, spinning
+    /// waits keep spinning. The time reported for a thread is how long the sync has been waiting
+    /// on that thread
+    pub fn set_stall_timeout(&self, timeout: Option<Duration>) {
+        let nanos = timeout.map_or(0, |t| t.as_nanos().clamp(1, u64::MAX as u128) as u64);
+        self.stall_timeout.store(nanos, Ordering::Relaxed);
+    }
+
+    /// choose how sync waits on threads that haven't passed through a quiescent state, the
+    /// default is WaitStrategy::Futex
+    pub fn set_wait_strategy(&self, strategy: WaitStrategy) {
+        self.wait_strategy
+            .store(strategy.encode(), Ordering::Relaxed);
+    }
+
+    /// the WaitStrategy set with set_wait_strategy
+    pub fn wait_strategy(&self) -> WaitStrategy {
+        WaitStrategy::decode(self.wait_strategy.load(Ordering::Relaxed))
+    }
+
+    /// number of grace periods completed by sync so far, wraps around
+    /// concurrent syncs share grace periods, so this can be a lot less than the number of syncs
+    pub fn grace_periods(&self) -> u32 {
+        self.gp_seq.load(Ordering::Acquire) / 2
+    }
+
+    fn stall_timeout(&self) -> Option<Duration> {
+        match self.stall_timeout.load(Ordering::Relaxed) {
+            0 => None,
+            nanos => Some(Duration::from_nanos(nanos)),
+        }
+    }
+
+    /// hand `value` off to be dropped by the reclaimer thread once a grace period has passed
+    /// never blocks, so writers can use it from hot paths instead of syncing or call_rcu
+    ///
+    /// values are only freed while the reclaimer is running, or when self is dropped
+    pub fn defer_free<T>(&self, value: Box<T>)
+    where
+        T: Send + 'static,
+    {
+        self.shared.deferred.push(Box::new(move || drop(value)));
+    }
+
+    /// rcu_barrier, block until every callback queued before the call, with call_rcu or
+    /// defer_drop on any handle or with defer_free, has run, e.g. before dropping something
+    /// the callbacks use
+    ///
+    /// callbacks queued on other handles are run by the caller once a grace period has passed.
+    /// The caller is not treated as quiescent, so it must not have an awake handle for self or
+    /// this deadlocks, put it to sleep first. It also waits for the callbacks already being
+    /// run, so it must not be called from a callback
+    pub fn barrier(&self) {
+        self.shared.barrier();
+    }
+
+    /// stop the reclaimer thread if it is running, values it was waiting on a grace period for
+    /// are kept for the next start_reclaimer or until self is dropped
+    ///
+    /// never waits for a grace period, so it is safe to call from a registered thread
+    pub fn stop_reclaimer(&self) {
+        let reclaimer = self.reclaimer.lock().unwrap().take();
+        if let Some(reclaimer) = reclaimer {
+            self.shared.deferred.stop.store(true, Ordering::Release);
+            self.shared.deferred.wake();
+            reclaimer.join().expect("rcu reclaimer thread panicked");
+        }
+    }
+
     /// internal only, used to simplify dropping thread handles
     fn lock(&self) -> <L as Lock<'_>>::Guard {
-        self.lock.lock()
+        self.shared.lock.lock()
     }
 
     /// Saftey: Need to ensure no other threads are referencing the given Tentry before it is
     /// dropped, this can be done by syncing, plus waiting for all other threads already syncing
     /// to finish
     unsafe fn remove(&self, elem: &Tentry) -> *mut RcuListElem<Tentry> {
-        unsafe { self.threads.remove_unsynced(elem) }
+        unsafe { self.shared.threads.remove_unsynced(elem) }.expect("thread is not registered")
+    }
+}
+
+impl<L> Qsbr<L>
+where
+    L: for<'a> Lock<'a> + Send + Sync + 'static,
+{
+    /// start a background thread that frees values handed off with defer_free in batches, much
+    /// like liburcu's call_rcu thread. Each batch waits for its own grace period without being
+    /// registered, so it never holds up syncs. Does nothing if already running
+    pub fn start_reclaimer(&self) {
+        let mut reclaimer = self.reclaimer.lock().unwrap();
+        if reclaimer.is_some() {
+            return;
+        }
+        self.shared.deferred.stop.store(false, Ordering::Release);
+        let shared = self.shared.clone();
+        let thread = thread::Builder::new()
+            .name("rcu-reclaimer".to_string())
+            .spawn(move || shared.reclaim())
+            .expect("failed to spawn rcu reclaimer thread");
+        *reclaimer = Some(thread);
+    }
+}
+
+impl<L> QsbrShared<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// body of the reclaimer thread, takes everything queued as a batch, polls until a grace
+    /// period has passed, then frees the batch
+    fn reclaim(&self) {
+        loop {
+            let pushed = self.deferred.pushed.load(Ordering::Acquire);
+            // started before taking the batch, so a barrier that takes the queue after us waits
+            // for it
+            let running = self.deferred.in_flight.start();
+            let batch = self.deferred.take();
+            if batch.is_empty() {
+                drop(running);
+                if self.deferred.stop.load(Ordering::Acquire) {
+                    return;
+                }
+                atomic_wait::wait(&self.deferred.pushed, pushed);
+                continue;
+            }
+            if !self.wait_for_grace_period(|| self.deferred.stop.load(Ordering::Acquire)) {
+                batch.into_iter().for_each(|f| self.deferred.push(f));
+                return;
+            }
+            batch.into_iter().for_each(|f| f());
+        }
+    }
+
+    /// take every queued callback, from the reclaimer's queue and every handle, wait for a
+    /// grace period and run them, then wait for the batches the reclaimer and handles had
+    /// already taken to finish running, see Qsbr::barrier
+    fn barrier(&self) {
+        let mut batch = self.deferred.take();
+        let mut in_flight = Vec::new();
+        let guard = self.lock.lock();
+        // Saftey: handles can't finish dropping, and so free their Tentry, until drop_sync gets
+        // the lock
+        for t in unsafe { self.threads.iter_unguarded() } {
+            let mut callbacks = t.callbacks.lock().unwrap();
+            let waiting = std::mem::take(&mut callbacks.waiting);
+            batch.extend(waiting.into_iter().flat_map(|(_, b)| b));
+            batch.append(&mut callbacks.pending);
+            // batches the handle already took out are run by it, so wait on those instead
+            in_flight.push((t.in_flight.clone(), t.in_flight.started()));
+            drop(callbacks);
+        }
+        drop(guard);
+        // a stopping reclaimer requeues its batch, so take the queue again once it is done
+        self.deferred
+            .in_flight
+            .wait(self.deferred.in_flight.started());
+        batch.append(&mut self.deferred.take());
+        if !batch.is_empty() {
+            self.wait_for_grace_period(|| false);
+            batch.into_iter().for_each(|f| f());
+        }
+        for (running, started) in in_flight {
+            running.wait(started);
+        }
+    }
+
+    /// wait for a grace period without being registered, polling since there is no handle to
+    /// be woken through, returns false if `stop` returned true first
+    fn wait_for_grace_period<F>(&self, stop: F) -> bool
+    where
+        F: FnMut() -> bool,
+    {
+        //Ordering: the callbacks' values were unpublished before being queued, so that needs to
+        //happen before the qstates are read, like in sync
+        atomic::fence(Ordering::SeqCst);
+        let snapshot = self.get_state();
+        poll_with_backoff(|| self.gp_elapsed(&snapshot), stop)
+    }
+
+    /// like QsbrThreadHandle::get_state, but for a thread that isn't registered
+    fn get_state(&self) -> GpSnapshot {
+        let guard = self.lock.lock();
+        // Saftey: Tentrys are only freed after their handle's drop_sync, which takes the lock
+        let state_copy = snapshot(unsafe { self.threads.iter_unguarded() });
+        drop(guard);
+        state_copy
+    }
+
+    /// like QsbrThreadHandle::gp_elapsed, but for a thread that isn't registered
+    fn gp_elapsed(&self, snapshot: &GpSnapshot) -> bool {
+        let guard = self.lock.lock();
+        // Saftey: see get_state
+        let elapsed = for_each_lagging(
+            unsafe { self.threads.iter_unguarded() },
+            snapshot,
+            None,
+            false,
+            |_, _| false,
+        );
+        drop(guard);
+        elapsed
+    }
+}
+
+/// deferred work handed off to the reclaimer, or queued with call_rcu
+type Deferred = Box<dyn FnOnce() + Send>;
+
+/// lock free stack of values handed off with Qsbr::defer_free, the reclaimer takes the whole
+/// stack at once so there is no ABA problem
+#[derive(Debug)]
+struct DeferredQueue {
+    head: AtomicPtr<DeferredNode>,
+    /// bumped after every push, the reclaimer sleeps on it while the queue is empty
+    pushed: AtomicU32,
+    /// tells the reclaimer to exit
+    stop: AtomicBool,
+    /// batches the reclaimer has taken
+    in_flight: InFlight,
+}
+
+struct DeferredNode {
+    f: Deferred,
+    next: *mut DeferredNode,
+}
+
+impl DeferredQueue {
+    fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            pushed: AtomicU32::new(0),
+            stop: AtomicBool::new(false),
+            in_flight: InFlight::default(),
+        }
+    }
+
+    fn push(&self, f: Deferred) {
+        let node = Box::into_raw(Box::new(DeferredNode {
+            f,
+            next: ptr::null_mut(),
+        }));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // Saftey: node isn't shared until the compare_exchange succeeds
+            unsafe { (*node).next = head };
+            //Ordering: Release so the reclaimer sees the node, and everything that happened
+            //before the push
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+        self.wake();
+    }
+
+    fn wake(&self) {
+        self.pushed.fetch_add(1, Ordering::Release);
+        atomic_wait::wake_all(&self.pushed);
+    }
+
+    /// take everything queued so far, oldest first
+    fn take(&self) -> Vec<Deferred> {
+        //Ordering: Release so a barrier taking the queue after the reclaimer sees its batch in
+        //in_flight
+        let mut next = self.head.swap(ptr::null_mut(), Ordering::AcqRel);
+        let mut batch = Vec::new();
+        while !next.is_null() {
+            // Saftey: the swap gave us ownership of every node
+            let node = unsafe { Box::from_raw(next) };
+            next = node.next;
+            batch.push(node.f);
+        }
+        batch.reverse();
+        batch
+    }
+}
+
+impl Drop for DeferredQueue {
+    fn drop(&mut self) {
+        self.take().into_iter().for_each(|f| f());
+    }
+}
+
+// Saftey: nodes are only reachable through head, and Deferred is Send
+unsafe impl Send for DeferredQueue {}
+unsafe impl Sync for DeferredQueue {}
+
+/// counts batches of callbacks taken out of a queue to be run without its lock held, so a
+/// barrier that missed them can wait for them instead. A queue's batches are taken and run by
+/// one thread at a time, so they finish in the order they were started
+#[derive(Debug, Default)]
+struct InFlight {
+    /// batches taken so far
+    started: AtomicU32,
+    /// batches that finished running, barriers sleep on it
+    finished: AtomicU32,
+}
+
+/// returned by InFlight::start, marks the batch finished when dropped, even if a callback
+/// panicked
+struct Running<'a>(&'a InFlight);
+
+impl InFlight {
+    /// call before taking a batch out of the queue
+    fn start(&self) -> Running<'_> {
+        self.started.fetch_add(1, Ordering::Relaxed);
+        Running(self)
+    }
+
+    fn started(&self) -> u32 {
+        self.started.load(Ordering::Relaxed)
+    }
+
+    /// block until the first `started` batches have finished running
+    fn wait(&self, started: u32) {
+        loop {
+            //Ordering: the batch's callbacks happen before the barrier returns
+            let finished = self.finished.load(Ordering::Acquire);
+            if seq_reached(finished, started) {
+                return;
+            }
+            atomic_wait::wait(&self.finished, finished);
+        }
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.finished.fetch_add(1, Ordering::Release);
+        atomic_wait::wake_all(&self.0.finished);
     }
 }
 
@@ -69,19 +434,38 @@ where
     /// create a new Qsbr
     fn new() -> Self {
         Self {
-            threads: RcuList::new(),
-            lock: L::new(),
+            shared: Arc::new(QsbrShared {
+                threads: RcuList::new(),
+                lock: L::new(),
+                deferred: DeferredQueue::new(),
+            }),
+            domain_id: new_domain_id(),
+            stall_timeout: AtomicU64::new(0),
+            gp_seq: AtomicU32::new(0),
+            wait_strategy: AtomicU64::new(WaitStrategy::Futex.encode()),
+            reclaimer: Mutex::new(None),
         }
     }
     /// register a new thread with Qsbr
     /// takes an unique id for this handle
     /// thread::current().id().as_u64().get() could be a good choice if std is available
-    fn register(&self, id: u64) -> Self::Handle<'_> {
-        let elem: &Tentry = self.threads.insert(Tentry::new(id));
-        QsbrThreadHandle {
+    /// sync relies on ids being unique, so fails if id is already registered
+    fn register(&self, id: u64) -> Result<Self::Handle<'_>, RegisterError> {
+        let elem: &Tentry = self
+            .shared
+            .threads
+            .try_insert(Tentry::new(id))
+            .map_err(|_| RegisterError::DuplicateId(id))?;
+        debug_assert!(self.shared.threads.is_strictly_ordered());
+        Ok(QsbrThreadHandle {
             info: elem,
             qsbr: self,
-        }
+            try_sync_cookie: None,
+            nesting: AtomicU32::new(0),
+        })
+    }
+    fn domain_id(&self) -> u64 {
+        self.domain_id
     }
 }
 
@@ -94,9 +478,135 @@ where
     qsbr: &'a Qsbr<L>,
     //needs to be an option so can set to None as part of Self::Drop
     info: &'a Tentry,
+    /// grace period started by try_sync, that hasn't completed yet
+    try_sync_cookie: Option<GpCookie>,
+    /// number of live guards from read(), read side critical sections nest
+    /// only touched by the thread using the handle, it is atomic so the handle stays Sync and
+    /// futures borrowing it, like AsyncThreadHandle's, stay Send
+    nesting: AtomicU32,
+}
+
+/// how a sync waits for a thread to pass through a quiescent state, spinning gets through a
+/// grace period faster, at the cost of burning a cpu while readers are still running
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaitStrategy {
+    /// busy loop until the thread passes through a quiescent state
+    Spin,
+    /// busy loop up to the given number of times, then sleep on a futex
+    SpinThenFutex(u32),
+    /// sleep on a futex straight away
+    #[default]
+    Futex,
+}
+
+impl WaitStrategy {
+    /// the variant in the high 32 bits, 0 is Futex, 1 SpinThenFutex and 2 Spin, and the spin
+    /// bound of SpinThenFutex in the low 32 bits, so every strategy round trips
+    fn encode(self) -> u64 {
+        match self {
+            Self::Futex => 0,
+            Self::SpinThenFutex(spins) => 1 << 32 | spins as u64,
+            Self::Spin => 2 << 32,
+        }
+    }
+
+    fn decode(encoded: u64) -> Self {
+        match encoded >> 32 {
+            0 => Self::Futex,
+            1 => Self::SpinThenFutex(encoded as u32),
+            _ => Self::Spin,
+        }
+    }
+
+    /// number of times to spin before sleeping, u32::MAX never sleeps
+    fn spins(self) -> u32 {
+        match self {
+            Self::Spin => u32::MAX,
+            Self::SpinThenFutex(spins) => spins,
+            Self::Futex => 0,
+        }
+    }
+
+    /// block until `qstate` is no longer `seen`
+    fn wait(self, qstate: &AtomicU32, seen: u32) {
+        let spins = self.spins();
+        let mut spun = 0;
+        //Ordering: callers fence after they are done waiting, so relaxed is fine
+        while qstate.load(Ordering::Relaxed) == seen {
+            if spins == u32::MAX || spun < spins {
+                spun += 1;
+                std::hint::spin_loop();
+            } else {
+                atomic_wait::wait(qstate, seen);
+            }
+        }
+    }
+
+    /// like wait, but calls `stalled` with how long it has waited every `timeout`
+    /// atomic_wait can't time out, so instead of sleeping on the futex it backs off polling
+    fn watched_wait<F>(self, qstate: &AtomicU32, seen: u32, timeout: Duration, mut stalled: F)
+    where
+        F: FnMut(Duration),
+    {
+        let spins = self.spins();
+        let start = Instant::now();
+        let mut next_warning = timeout;
+        let mut spun = 0;
+        let mut backoff = Duration::from_micros(1);
+        //Ordering: see wait
+        while qstate.load(Ordering::Relaxed) == seen {
+            if spins == u32::MAX || spun < spins {
+                spun += 1;
+                std::hint::spin_loop();
+            } else {
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(Duration::from_millis(1));
+            }
+            let waited = start.elapsed();
+            if waited >= next_warning {
+                stalled(waited);
+                next_warning = waited + timeout;
+            }
+        }
+    }
+}
+
+/// returned by QsbrThreadHandle::sync_timeout when a grace period didn't complete in time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncTimeout {
+    /// ids of the threads that hadn't passed through a quiescent state
+    pub stalled: Vec<u64>,
+}
+
+impl fmt::Display for SyncTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sync timed out waiting on threads {:?}", self.stalled)
+    }
 }
 
-impl<L> RcuGuard<'_> for QsbrGuard<'_, L> where L: for<'lock> Lock<'lock> {}
+impl std::error::Error for SyncTimeout {}
+
+/// returned by QsbrThreadHandle::start_grace_period, used to poll whether a grace period has
+/// passed since it was created
+#[derive(Debug)]
+pub struct GpCookie {
+    snapshot: GpSnapshot,
+    /// see RCU::domain_id, the snapshot is only meaningful for the instance it was taken from
+    domain_id: u64,
+}
+
+/// the qstate of every registered Tentry at some point in time, a grace period has passed once
+/// every Tentry that wasn't in a quiescent state when the snapshot was taken has changed qstate
+pub(crate) type GpSnapshot = Vec<(u64, u32)>;
+
+impl<L> RcuGuard<'_> for QsbrGuard<'_, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    fn domain_id(&self) -> u64 {
+        self.domain_id
+    }
+}
 
 impl<'a, L> RcuHandle<'a> for QsbrThreadHandle<'a, L>
 where
@@ -105,29 +615,36 @@ where
     type Guard<'b> = QsbrGuard<'b, L> where Self: 'a, 'a: 'b;
     type Sleeper<'b> = QsbrSleeper<'b, L> where Self: 'a, 'a: 'b;
     fn sleep(self) -> Self::Sleeper<'a> {
+        self.check_not_reading("sleep");
         self.info.qstate.store(2, Ordering::Release);
         Self::Sleeper { handle: self }
     }
     /// read starts an rcu critical section, which lasts until the returned
-    /// QsbrGuard is dropped, is a no op, but used to ensure liveness of references
-    /// by stop quescent_state from being called
-    fn read(&self) -> Self::Guard<'a> {
+    /// QsbrGuard is dropped, only bumps a nesting counter, but used to ensure liveness of
+    /// references by stop quescent_state from being called
+    /// critical sections can be nested, the outermost guard ends it
+    fn read(&self) -> Self::Guard<'_> {
+        //Ordering: only this thread reads it
+        self.nesting.fetch_add(1, Ordering::Relaxed);
         QsbrGuard {
-            _thread_handle: &PhantomData,
+            nesting: &self.nesting,
+            domain_id: self.qsbr.domain_id,
+            _lock: PhantomData,
         }
     }
+    fn is_reading(&self) -> bool {
+        self.nesting.load(Ordering::Relaxed) != 0
+    }
+    fn domain_id(&self) -> u64 {
+        self.qsbr.domain_id
+    }
     /// quiescent_state is use to signal to the Qsbr that this thread has passed
     /// through a quiescent state. If this method is not called frequent enough
     /// other QsbrThreadHandle calling sync will block, reducing performance and
     /// in pathilogical cases, causing the program to crash due to OOMing
     fn quiescent_state(&mut self) {
-        //Ordering: no other thread should be updating qstate, so relaxed is safe
-        //make sure we don't accidentally wrap
-        if self.info.qstate.fetch_add(1, Ordering::Release) > u32::MAX / 2 {
-            //Ordering: needs to happen before the sync that sees it
-            self.info.qstate.store(10, Ordering::Release);
-        }
-        atomic_wait::wake_all(&self.info.qstate);
+        self.mark_quiescent();
+        self.run_deferred();
     }
 
     fn quiescent_sync(&mut self) {
@@ -144,82 +661,351 @@ where
 
     /// Used to synchronize all QsbrThreadHandles, blocks until all handles have
     /// called quiescent_state, signalling that a grace period has passed
+    ///
+    /// this thread is treated as quiescent while waiting, and syncs that start while another
+    /// sync is already waiting on readers share the next grace period instead of each walking
+    /// the thread list, so N concurrent syncs cost about one grace period
     fn sync(&self) {
-        //TODO
-        // split code into 3 parts:
-        // 1: create `local_copy` - a list of all Tentrys and their status
-        // 2: `try_sync` - tries to sync, returns Ok if syned, Err is not
-        // 3: loop over try_sync
-        // This will be useful for future api expansion, ex: if an item came be removed from a
-        // shared struct, but is needed for a while before dropping the sync list can be created
-        // after the removal, but before the private computation, making the final drop likely
-        // to go faster. Or for batching elements to drop together e.g. an async drop impl
-        // also, a different try_sync_internal method can be used for the special sync needed before
-        // dropping a Tentry
-
-        // Ordering: set long term quescent state
-        //let prev_state = self.info.qstate.swap(1, Ordering::Release);
+        let prev_state = self.enter_sync();
 
-        let local_copy = self.get_state();
-
-        let mut before = local_copy.into_iter();
-        let mut b = if let Some(v) = before.next() {
-            v
-        } else {
-            return;
-        };
-        let guard = self.read();
-        for after in RcuListIterator::new(&guard, &self.qsbr.threads) {
-            // skip over this thread since we know it is in a quescent state (covered by b.1 == 1)
-            // skip over threads in a long quescent state ( < 10 )
-            while b.0 < after.id || b.1 < 10 {
-                b = if let Some(v) = before.next() {
-                    v
-                } else {
-                    return;
-                };
+        //Ordering: removals before the sync need to be visible to whichever thread drives the
+        //grace period we wait on, pairs with the fence after the driver's compare_exchange
+        atomic::fence(Ordering::SeqCst);
+        let target = gp_target(self.qsbr.gp_seq.load(Ordering::Acquire));
+        loop {
+            let seq = self.qsbr.gp_seq.load(Ordering::Acquire);
+            if seq_reached(seq, target) {
+                break;
             }
-            // after didn't exist when sync started, so move on to next elem
-            if b.0 > after.id {
+            if seq & 1 == 1 {
+                // another sync is driving a grace period, wait for it to finish
+                atomic_wait::wait(&self.qsbr.gp_seq, seq);
                 continue;
             }
-            //assert!(b.0 == after.id);
-            //let mut qstate = after.qstate.load(Ordering::Relaxed);
-            // qstate only changes when passing through a quescent state
-            // already filtered out threads that started in a long quescent state so don't need
-            // to check that again here in the hot loop
-            // TODO check if futex (atomic_wait::wait) or busy loop is better
-            // might want to have an option to sync either way, or a hybrid approach depending on
-            // how hot the call to sync is
-            while b.1 == after.qstate.load(Ordering::Relaxed) {
-                atomic_wait::wait(&after.qstate, b.1);
+            if self
+                .qsbr
+                .gp_seq
+                .compare_exchange(
+                    seq,
+                    seq.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                //Ordering: the start of the grace period happens before the qstates are read
+                atomic::fence(Ordering::SeqCst);
+                self.wait_for_readers(self.qsbr.wait_strategy());
+                //Ordering: readers passing through a quiescent state happen before the waiters
+                //see the grace period completed
+                self.qsbr
+                    .gp_seq
+                    .store(seq.wrapping_add(2), Ordering::Release);
+                atomic_wait::wake_all(&self.qsbr.gp_seq);
             }
         }
-        //Ordering: make sure all the Tentry's passed through a quescent state before returning
-        atomic::fence(Ordering::Acquire);
-
-        //Ordering: passed through a quescent state while syncing
-        //if prev_state > u32::MAX / 2 {
-        //    self.info.qstate.store(10, Ordering::Release);
-        //} else {
-        //    self.info.qstate.store(prev_state + 1, Ordering::Release);
-        //}
-        atomic_wait::wake_all(&self.info.qstate);
+        self.exit_sync(prev_state);
     }
 }
 
-impl<L> QsbrThreadHandle<'_, L>
+impl<'a, L> QsbrThreadHandle<'a, L>
 where
     L: for<'lock> Lock<'lock>,
 {
-    fn get_state(&self) -> Vec<(u64, u32)> {
+    /// the grace period itself, blocks until every thread that wasn't quiescent when it was
+    /// called has passed through a quiescent state, only the thread driving a grace period in
+    /// sync calls this
+    fn wait_for_readers(&self, strategy: WaitStrategy) {
+        let local_copy = self.get_state();
+        self.wait_for_lagging(&local_copy, false, strategy);
+    }
+
+    /// sync that doesn't share grace periods and busy polls every thread it waits on, whatever
+    /// the Qsbr's wait strategy is. Gets through a grace period as fast as the readers allow,
+    /// for latency critical updates, at the cost of burning a cpu while it waits. With the stall
+    /// watchdog enabled it still spins, and only adds the stall warnings
+    ///
+    /// this thread is treated as quiescent, so this must not be called inside a read side
+    /// critical section
+    pub fn sync_expedited(&self) {
+        let prev_state = self.enter_sync();
+        //Ordering: removals before the sync happen before the qstates are read
+        atomic::fence(Ordering::SeqCst);
+        self.wait_for_readers(WaitStrategy::Spin);
+        self.exit_sync(prev_state);
+    }
+
+    /// in debug builds, panic if this thread is inside a read side critical section, since
+    /// `op` treats it as quiescent
+    fn check_not_reading(&self, op: &str) {
+        debug_assert!(
+            self.nesting.load(Ordering::Relaxed) == 0,
+            "{} called inside a read side critical section",
+            op
+        );
+    }
+
+    /// mark this thread as syncing, so it is treated as quiescent while waiting, returns the
+    /// qstate to pass to exit_sync
+    fn enter_sync(&self) -> u32 {
+        self.check_not_reading("sync");
+        // Ordering: set long term quescent state, quiescent_sync has already done this
+        let prev_state = self.info.qstate.load(Ordering::Relaxed);
+        if prev_state >= 10 {
+            self.info.qstate.store(1, Ordering::Release);
+        }
+        // a thread driving a grace period may be sleeping on our qstate, and could be the one we
+        // end up waiting on
+        atomic_wait::wake_all(&self.info.qstate);
+        prev_state
+    }
+
+    fn exit_sync(&self, prev_state: u32) {
+        //Ordering: passed through a quescent state while syncing
+        if prev_state >= 10 {
+            if prev_state > u32::MAX / 2 {
+                self.info.qstate.store(10, Ordering::Release);
+            } else {
+                self.info.qstate.store(prev_state + 1, Ordering::Release);
+            }
+        }
+        atomic_wait::wake_all(&self.info.qstate);
+    }
+
+    /// signal a quiescent state without running deferred callbacks, only needs a shared
+    /// reference so wrappers like AsyncThreadHandle can call it from any of their methods
+    pub(crate) fn mark_quiescent(&self) {
+        self.check_not_reading("quiescent_state");
+        //Ordering: no other thread should be updating qstate, so relaxed is safe
+        //make sure we don't accidentally wrap
+        if self.info.qstate.fetch_add(1, Ordering::Release) > u32::MAX / 2 {
+            //Ordering: needs to happen before the sync that sees it
+            self.info.qstate.store(10, Ordering::Release);
+        }
+        atomic_wait::wake_all(&self.info.qstate);
+    }
+
+    /// snapshot the state of every registered thread, the grace period is over once
+    /// poll_grace_period returns true
+    /// e.g. remove an element from a shared structure, start a grace period, do other work and
+    /// poll until the element can be dropped
+    pub fn start_grace_period(&self) -> GpCookie {
+        GpCookie {
+            snapshot: self.get_state(),
+            domain_id: self.qsbr.domain_id,
+        }
+    }
+
+    /// check if a grace period has passed since `cookie` was created, never blocks
+    /// this thread is treated as quiescent, so this must not be called inside a read side
+    /// critical section
+    pub fn poll_grace_period(&self, cookie: &GpCookie) -> bool {
+        self.check_not_reading("poll_grace_period");
+        assert_eq!(
+            cookie.domain_id, self.qsbr.domain_id,
+            "grace period cookie is from a different rcu instance"
+        );
+        self.gp_elapsed(&cookie.snapshot)
+    }
+
+    /// non blocking sync, for event loops that can't sleep waiting on other threads
+    /// the first call starts a grace period, and later calls poll it, returns true once a grace
+    /// period that started after the first call has passed, the next call then starts a new one
+    /// this thread is treated as quiescent, so this must not be called inside a read side
+    /// critical section
+    pub fn try_sync(&mut self) -> bool {
+        let cookie = match self.try_sync_cookie.take() {
+            Some(cookie) => cookie,
+            None => self.start_grace_period(),
+        };
+        if self.poll_grace_period(&cookie) {
+            return true;
+        }
+        self.try_sync_cookie = Some(cookie);
+        false
+    }
+
+    /// like sync, but gives up after `timeout`, returning the ids of the threads that haven't
+    /// passed through a quiescent state yet
+    /// this thread is treated as quiescent, so this must not be called inside a read side
+    /// critical section
+    pub fn sync_timeout(&self, timeout: Duration) -> Result<(), SyncTimeout> {
+        let deadline = Instant::now() + timeout;
+        // treated as quiescent while polling, so concurrent callers don't wait on each other
+        let prev_state = self.enter_sync();
+        let cookie = self.start_grace_period();
+        let mut result = Ok(());
+        if !poll_with_backoff(
+            || self.poll_grace_period(&cookie),
+            || Instant::now() >= deadline,
+        ) {
+            let stalled = self.lagging(&cookie.snapshot);
+            if !stalled.is_empty() {
+                result = Err(SyncTimeout {
+                    stalled: stalled.into_iter().map(|(id, _)| id).collect(),
+                });
+            }
+        }
+        self.exit_sync(prev_state);
+        result
+    }
+
+    /// queue `f` to be run once a grace period has passed, without blocking the caller
+    ///
+    /// callbacks are only run from this handle's quiescent_state(), flush_deferred(), when the
+    /// handle is dropped, or by Qsbr::barrier, so a thread that never passes through a quiescent
+    /// state never reclaims
+    ///
+    /// `f` must be 'static, since leaking the handle with mem::forget would otherwise let it
+    /// outlive what it borrows
+    ///
+    /// callbacks run in batches, if one panics the panic propagates to whoever is running the
+    /// batch and the rest of it is dropped without being run
+    pub fn call_rcu<F>(&mut self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.info
+            .callbacks
+            .lock()
+            .unwrap()
+            .pending
+            .push(Box::new(f));
+    }
+
+    /// drop `value` once a grace period has passed, see call_rcu
+    pub fn defer_drop<T>(&mut self, value: T)
+    where
+        T: Send + 'static,
+    {
+        self.call_rcu(move || drop(value));
+    }
+
+    /// block until a grace period has passed, then run every callback queued by call_rcu
+    pub fn flush_deferred(&mut self) {
+        let mut callbacks = self.info.callbacks.lock().unwrap();
+        if callbacks.pending.is_empty() && callbacks.waiting.is_empty() {
+            return;
+        }
+        let waiting = std::mem::take(&mut callbacks.waiting);
+        let pending = std::mem::take(&mut callbacks.pending);
+        // callbacks are run without the lock, so they can queue more, a barrier that missed
+        // them waits on in_flight instead
+        let running = self.info.in_flight.start();
+        drop(callbacks);
+        self.quiescent_sync();
+        waiting
+            .into_iter()
+            .flat_map(|(_, batch)| batch)
+            .chain(pending)
+            .for_each(|f| f());
+        drop(running);
+    }
+
+    /// snapshot the callbacks queued since the last call, then run every batch whose grace
+    /// period has passed, never blocks
+    /// must only be called from a quiescent state since this thread is treated as quiescent
+    fn run_deferred(&mut self) {
+        let mut callbacks = self.info.callbacks.lock().unwrap();
+        if !callbacks.pending.is_empty() {
+            let batch = std::mem::take(&mut callbacks.pending);
+            callbacks.waiting.push_back((self.get_state(), batch));
+        }
+        let mut ready = Vec::new();
+        while let Some((snapshot, _)) = callbacks.waiting.front() {
+            if !self.gp_elapsed(snapshot) {
+                break;
+            }
+            if let Some((_, batch)) = callbacks.waiting.pop_front() {
+                ready.push(batch);
+            }
+        }
+        if ready.is_empty() {
+            return;
+        }
+        // see flush_deferred
+        let running = self.info.in_flight.start();
+        drop(callbacks);
+        ready.into_iter().flatten().for_each(|f| f());
+        drop(running);
+    }
+
+    /// non blocking version of the sync loop, checks if every Tentry that wasn't quiescent when
+    /// `snapshot` was taken has since passed through a quiescent state
+    /// this thread is treated as quiescent
+    pub(crate) fn gp_elapsed(&self, snapshot: &GpSnapshot) -> bool {
+        self.for_each_lagging(snapshot, false, |_, _| false)
+    }
+
+    /// ids and snapshotted qstates of every Tentry that hasn't passed through a quiescent state
+    /// since `snapshot` was taken
+    /// this thread is treated as quiescent
+    fn lagging(&self, snapshot: &GpSnapshot) -> Vec<(u64, u32)> {
+        let mut lagging = Vec::new();
+        self.for_each_lagging(snapshot, false, |t, qstate| {
+            lagging.push((t.id, qstate));
+            true
+        });
+        lagging
+    }
+
+    /// see the free function for_each_lagging, this thread is skipped
+    fn for_each_lagging<F>(&self, snapshot: &GpSnapshot, wait_for_syncers: bool, f: F) -> bool
+    where
+        F: for<'t> FnMut(&'t Tentry, u32) -> bool,
+    {
         let guard = self.read();
-        let state_copy: Vec<(u64, u32)> = RcuListIterator::new(&guard, &self.qsbr.threads)
-            .map(|e: &Tentry| (e.id, e.qstate.load(Ordering::Relaxed)))
-            .collect();
-        // make sure state_copy "happened before" fn return
-        atomic::fence(Ordering::Acquire);
-        state_copy
+        for_each_lagging(
+            RcuListIterator::new(&guard, &self.qsbr.shared.threads),
+            snapshot,
+            Some(self.info.id),
+            wait_for_syncers,
+            f,
+        )
+    }
+
+    /// block until every Tentry that wasn't quiescent when `snapshot` was taken has passed
+    /// through a quiescent state, the blocking version of gp_elapsed
+    /// with the stall watchdog enabled, logs each thread it has waited on for too long
+    fn wait_for_lagging(
+        &self,
+        snapshot: &GpSnapshot,
+        wait_for_syncers: bool,
+        strategy: WaitStrategy,
+    ) {
+        self.for_each_lagging(snapshot, wait_for_syncers, |t, seen| {
+            // a syncing thread may be in drop_sync's loop waiting on us, so it can't be slept on
+            // without risking both sleeping, due to the lock around drop_sync any other syncing
+            // thread is in a regular sync
+            let strategy = if seen == 1 {
+                WaitStrategy::Spin
+            } else {
+                strategy
+            };
+            match self.qsbr.stall_timeout() {
+                None => strategy.wait(&t.qstate, seen),
+                Some(timeout) => strategy.watched_wait(&t.qstate, seen, timeout, |stuck_for| {
+                    log::warn!(
+                        "rcu stall: thread {} hasn't passed through a quiescent state for {:?} (qstate {}), thread {} is waiting on it",
+                        t.id,
+                        stuck_for,
+                        seen,
+                        self.info.id
+                    );
+                }),
+            }
+            true
+        });
+    }
+
+    /// the id this handle was registered with
+    pub(crate) fn id(&self) -> u64 {
+        self.info.id
+    }
+
+    pub(crate) fn get_state(&self) -> GpSnapshot {
+        let guard = self.read();
+        snapshot(RcuListIterator::new(&guard, &self.qsbr.shared.threads))
     }
 
     // basically the same as regular sync, EXCEPT syncing doesn't count as a quescent state
@@ -234,62 +1020,91 @@ where
         let guard = self.qsbr.lock();
         self.info.qstate.store(1, Ordering::Release);
         let local_copy = self.get_state();
+        // unlike wait_for_readers, syncing threads aren't treated as quiescent, since they may be
+        // walking the thread list and still referencing this Tentry
+        self.wait_for_lagging(&local_copy, true, self.qsbr.wait_strategy());
+        self.info.qstate.store(0, Ordering::Release);
+        atomic_wait::wake_all(&self.info.qstate);
+        drop(guard);
+    }
+}
 
-        let mut before = local_copy.into_iter();
-        let mut b = if let Some(v) = before.next() {
-            v
-        } else {
-            return;
-        };
-        let my_guard = self.read();
-        for after in RcuListIterator::new(&my_guard, &self.qsbr.threads) {
-            // skip over this thread since we know it is in a quescent state
-            // skip over threads in a long quescent state, but not syncing ( < 10 && != 1 )
-            while b.0 < after.id || b.0 == self.info.id || ((b.1 < 10) && (b.1 != 1)) {
-                b = if let Some(v) = before.next() {
-                    v
-                } else {
-                    return;
-                };
-            }
-            // after didn't exist when sync started, so move on to next elem
-            if b.0 > after.id {
-                continue;
-            }
-            // deadlock condition, if 2 threads are running drop_sync might both sleep waiting on the
-            // other to finish so busy loop if b is in a special state, which should only be b.1 ==
-            // 1 due to the while loop above. due to the lock around drop_sync any threads in state
-            //   1 (syncing) besides self are in a regular sync
-            if b.1 != 1 {
-                while b.1 == after.qstate.load(Ordering::Relaxed) {
-                    atomic_wait::wait(&after.qstate, b.1);
-                }
+/// poll until `elapsed` returns true, backing off exponentially since atomic_wait can't time
+/// out, returns false if `stop` returned true first
+fn poll_with_backoff<E, S>(mut elapsed: E, mut stop: S) -> bool
+where
+    E: FnMut() -> bool,
+    S: FnMut() -> bool,
+{
+    let mut backoff = Duration::from_micros(1);
+    while !elapsed() {
+        if stop() {
+            return false;
+        }
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(Duration::from_millis(1));
+    }
+    true
+}
+
+/// snapshot the qstate of every Tentry in `threads`
+fn snapshot<'t>(threads: impl Iterator<Item = &'t Tentry>) -> GpSnapshot {
+    let state_copy: Vec<(u64, u32)> = threads
+        .map(|e: &Tentry| (e.id, e.qstate.load(Ordering::Relaxed)))
+        .collect();
+    // make sure state_copy "happened before" fn return
+    atomic::fence(Ordering::Acquire);
+    state_copy
+}
+
+/// calls `f` with each Tentry in `threads` that hasn't passed through a quiescent state since
+/// `snapshot` was taken and its snapshotted qstate, stopping early if `f` returns false
+/// `skip` is the calling thread's id, which is treated as quiescent
+/// threads that were in a long quiescent state are skipped, except syncing ones if
+/// `wait_for_syncers` is set, since they are still walking the thread list
+/// returns true if the grace period has passed
+fn for_each_lagging<'t, F>(
+    threads: impl Iterator<Item = &'t Tentry>,
+    snapshot: &GpSnapshot,
+    skip: Option<u64>,
+    wait_for_syncers: bool,
+    mut f: F,
+) -> bool
+where
+    F: FnMut(&'t Tentry, u32) -> bool,
+{
+    let mut elapsed = true;
+    let mut before = snapshot.iter();
+    let mut b = if let Some(v) = before.next() {
+        v
+    } else {
+        return true;
+    };
+    let long_quiescent = |qstate: u32| qstate < 10 && !(wait_for_syncers && qstate == 1);
+    for after in threads {
+        // skip this thread and threads that started in a long quescent state
+        while b.0 < after.id || long_quiescent(b.1) || Some(b.0) == skip {
+            b = if let Some(v) = before.next() {
+                v
             } else {
-                //assert!(b.0 == after.id);
-                let mut qstate = after.qstate.load(Ordering::Relaxed);
-                // qstate only changes when passing through a quescent state
-                // already filtered out threads that started in a long quescent state so don't need
-                // to check that again here in the hot loop
-                // don't need to check for syncing threads either, since either they started after the
-                // thandle was removed from the list, they are no longer syncing, or their qstate
-                // hasn't changed
-                // TODO UNOPTIMIZED if multiple threads are syncing it would be better to have all but
-                // one sleep (on a cond var?) otherwise cache contention will make this slower, plus
-                // wasted cpu busy looping
-                while qstate == b.1 {
-                    std::hint::spin_loop();
-                    //Ordering: Acq fence after for loop ensures qstate seen has already happened, so
-                    //can be relaxed here
-                    qstate = after.qstate.load(Ordering::Relaxed);
-                }
+                atomic::fence(Ordering::Acquire);
+                return elapsed;
+            };
+        }
+        // after didn't exist when the snapshot was taken
+        if b.0 > after.id {
+            continue;
+        }
+        if b.1 == after.qstate.load(Ordering::Relaxed) {
+            elapsed = false;
+            if !f(after, b.1) {
+                return false;
             }
         }
-        //Ordering: make sure all the Tentry's passed through a quescent state before returning
-        atomic::fence(Ordering::Acquire);
-        self.info.qstate.store(0, Ordering::Release);
-        atomic_wait::wake_all(&self.info.qstate);
-        drop(guard);
     }
+    //Ordering: make sure all the Tentry's passed through a quescent state before returning
+    atomic::fence(Ordering::Acquire);
+    elapsed
 }
 
 //unregistering a thread
@@ -297,10 +1112,20 @@ impl<L> Drop for QsbrThreadHandle<'_, L>
 where
     L: for<'a> Lock<'a>,
 {
-    /// unregisters the given handle with Qsbr
+    /// unregisters the given handle with Qsbr, then runs its queued callbacks
     fn drop(&mut self) {
+        // the callbacks are only run once the Tentry is gone, so a panicking one can't leave it
+        // registered and hold up every later sync
+        let mut callbacks = self.info.callbacks.lock().unwrap();
+        let waiting = std::mem::take(&mut callbacks.waiting);
+        let pending = std::mem::take(&mut callbacks.pending);
+        // see flush_deferred, the Tentry is freed before they run so keep in_flight alive
+        let in_flight = self.info.in_flight.clone();
+        let running = in_flight.start();
+        drop(callbacks);
         let tentry_ptr = unsafe { self.qsbr.remove(self.info) };
 
+        // also the grace period the callbacks wait for, since it waits on every other thread
         self.drop_sync();
         // nothing should have a ptr to this anymore, but just in case
         unsafe { (*tentry_ptr).elem.qstate.store(0, Ordering::Release) };
@@ -308,6 +1133,12 @@ where
         //which _usually_ is a terrible idea, but since we know no one else has a reference to
         //it anymore since we removed it from the list, and did a drop_sync it is safe
         let _ = unsafe { Box::from_raw(tentry_ptr) };
+        waiting
+            .into_iter()
+            .flat_map(|(_, batch)| batch)
+            .chain(pending)
+            .for_each(|f| f());
+        drop(running);
     }
 }
 
@@ -320,8 +1151,10 @@ pub struct QsbrGuard<'a, L>
 where
     L: for<'lock> Lock<'lock>,
 {
-    _thread_handle: &'a PhantomData<L>,
-    //thread_handle: &'a QsbrThreadHandle<'a, L>,
+    /// the handle's nesting counter
+    nesting: &'a AtomicU32,
+    domain_id: u64,
+    _lock: PhantomData<L>,
 }
 
 // end the rcu critical section
@@ -329,9 +1162,9 @@ impl<L> Drop for QsbrGuard<'_, L>
 where
     L: for<'a> Lock<'a>,
 {
-    /// ends the critical section
+    /// ends the critical section, if this is the outermost guard
     fn drop(&mut self) {
-        //QsbrThreadHandle unlock(), which is currently a noop
+        self.nesting.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
@@ -362,6 +1195,30 @@ struct Tentry {
     /// you really shouldn't do that, but if you do you can't use the threadid since the id needs
     /// to be unique for each tentry
     id: u64,
+    /// callbacks queued with call_rcu, kept here instead of in the handle so Qsbr::barrier can
+    /// reach them
+    callbacks: Mutex<Callbacks>,
+    /// batches taken out of callbacks to be run by the handle, only started with callbacks
+    /// locked. Shared so a barrier can wait on it after the Tentry is freed
+    in_flight: Arc<InFlight>,
+}
+
+/// callbacks queued by a QsbrThreadHandle
+#[derive(Default)]
+struct Callbacks {
+    /// queued by call_rcu since the last quiescent_state
+    pending: Vec<Deferred>,
+    /// batches waiting on a grace period, oldest first
+    waiting: VecDeque<(GpSnapshot, Vec<Deferred>)>,
+}
+
+impl fmt::Debug for Callbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Callbacks")
+            .field("pending", &self.pending.len())
+            .field("waiting", &self.waiting.len())
+            .finish()
+    }
 }
 
 impl Tentry {
@@ -371,6 +1228,8 @@ impl Tentry {
         Tentry {
             qstate: AtomicU32::new(10),
             id,
+            callbacks: Mutex::new(Callbacks::default()),
+            in_flight: Arc::default(),
         }
     }
 }
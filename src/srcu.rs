@@ -0,0 +1,224 @@
+use crate::cds::rculist::*;
+use crate::utils::{self, Lock, ReaderEntry, Sleeper};
+use crate::{new_domain_id, RcuGuard, RcuHandle, RegisterError, RCU};
+use std::marker::PhantomData;
+use std::sync::atomic::{self, AtomicU32, Ordering};
+
+/// Srcu sleepable rcu
+/// each Srcu is an independent domain, a sync only waits for readers of its own domain, so a
+/// reader that blocks (e.g. doing I/O) inside a critical section only holds up writers of that
+/// domain. Readers count themselves in one of two indices, and sync flips the index and waits
+/// for the old one to drain, so like Mb threads never need to call quiescent_state
+#[derive(Debug)]
+pub struct Srcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    threads: RcuList<SrcuEntry, Self, L>,
+    /// low bit is the index new readers count themselves in
+    idx: AtomicU32,
+    /// 1 while a sync is waiting on readers, readers wake it when an index drains
+    gp_futex: AtomicU32,
+    /// serializes syncs, and unregistering threads so a sync never sees a freed SrcuEntry
+    lock: L,
+    /// see RCU::domain_id
+    domain_id: u64,
+}
+
+impl<L> Default for Srcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L> RCU for Srcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    type Handle<'a> = SrcuThreadHandle<'a, L> where L: 'a;
+    /// create a new Srcu domain
+    fn new() -> Self {
+        Self {
+            threads: RcuList::new(),
+            idx: AtomicU32::new(0),
+            gp_futex: AtomicU32::new(0),
+            lock: L::new(),
+            domain_id: new_domain_id(),
+        }
+    }
+    /// register a new thread with this domain
+    /// takes an unique id for this handle, fails if id is already registered
+    fn register(&self, id: u64) -> Result<Self::Handle<'_>, RegisterError> {
+        let elem: &SrcuEntry = self
+            .threads
+            .try_insert(SrcuEntry::new(id, [AtomicU32::new(0), AtomicU32::new(0)]))
+            .map_err(|_| RegisterError::DuplicateId(id))?;
+        debug_assert!(self.threads.is_strictly_ordered());
+        Ok(SrcuThreadHandle {
+            info: elem,
+            srcu: self,
+        })
+    }
+    fn domain_id(&self) -> u64 {
+        self.domain_id
+    }
+}
+
+impl<L> Srcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// flip the index new readers use, then wait for every reader counted in the old one to
+    /// leave its critical section, see utils::flip_and_wait
+    /// caller must hold self.lock
+    fn flip_and_wait(&self) {
+        let flip = || self.idx.fetch_xor(1, Ordering::Relaxed) as usize & 1;
+        let in_old_phase =
+            |readers: &[AtomicU32; 2], old: &usize| readers[*old].load(Ordering::Relaxed) != 0;
+        // Saftey: self.lock is held, so no SrcuEntry can be removed
+        unsafe {
+            utils::flip_and_wait(
+                &self.threads,
+                &self.gp_futex,
+                || atomic::fence(Ordering::SeqCst),
+                flip,
+                in_old_phase,
+            )
+        };
+    }
+}
+
+///created via Srcu::register(), used to register a thread with the Srcu
+pub struct SrcuThreadHandle<'a, L>
+where
+    L: for<'b> Lock<'b>,
+    Self: 'a,
+{
+    srcu: &'a Srcu<L>,
+    info: &'a SrcuEntry,
+}
+
+impl<'a, L> RcuHandle<'a> for SrcuThreadHandle<'a, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    type Guard<'b> = SrcuGuard<'b, L> where Self: 'a, 'a: 'b;
+    type Sleeper<'b> = SrcuSleeper<'b, L> where Self: 'a, 'a: 'b;
+    /// readers are only active inside a critical section, so sleeping is a no op
+    fn sleep(self) -> Self::Sleeper<'a> {
+        Self::Sleeper { handle: self }
+    }
+    /// read starts an rcu critical section, which lasts until the returned SrcuGuard is
+    /// dropped, and unlike Qsbr may block
+    fn read(&self) -> Self::Guard<'_> {
+        let idx = self.srcu.idx.load(Ordering::Relaxed) as usize & 1;
+        self.info.ctr[idx].fetch_add(1, Ordering::Relaxed);
+        //Ordering: the count needs to be visible before any reads in the critical section,
+        //pairs with the fences in flip_and_wait
+        atomic::fence(Ordering::SeqCst);
+        SrcuGuard {
+            idx,
+            info: self.info,
+            srcu: self.srcu,
+            _not_send: PhantomData,
+        }
+    }
+    /// readers track their own critical sections, so this is a no op
+    fn quiescent_state(&mut self) {}
+
+    fn quiescent_sync(&mut self) {
+        self.sync();
+    }
+
+    /// Used to synchronize this domain, blocks until every read side critical section of this
+    /// domain that started before sync was called has ended
+    /// calling sync inside a critical section of the same domain deadlocks
+    fn sync(&self) {
+        let guard = self.srcu.lock.lock();
+        //Ordering: removals before sync need to be visible before checking readers
+        atomic::fence(Ordering::SeqCst);
+        // two flips are needed, a reader could have loaded the old index just before the first
+        // flip but not counted itself in it until after we checked it
+        self.srcu.flip_and_wait();
+        self.srcu.flip_and_wait();
+        //Ordering: make sure all the readers finished before returning
+        atomic::fence(Ordering::SeqCst);
+        drop(guard);
+    }
+
+    fn is_reading(&self) -> bool {
+        self.info.ctr.iter().any(|r| r.load(Ordering::Relaxed) != 0)
+    }
+
+    fn domain_id(&self) -> u64 {
+        self.srcu.domain_id
+    }
+}
+
+//unregistering a thread
+impl<L> Drop for SrcuThreadHandle<'_, L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// unregisters the given handle with Srcu
+    fn drop(&mut self) {
+        utils::unregister(&self.srcu.threads, &self.srcu.lock, self.info);
+    }
+}
+
+pub type SrcuSleeper<'a, L> = Sleeper<SrcuThreadHandle<'a, L>>;
+
+/// SrcuGuard, counts its thread as reading in one of the domain's two indices until it is
+/// dropped
+pub struct SrcuGuard<'a, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    idx: usize,
+    info: &'a SrcuEntry,
+    srcu: &'a Srcu<L>,
+    // ctr must only ever be written by the thread that owns it
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<L> SrcuGuard<'_, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    /// the index this critical section is counted in, like the value srcu_read_lock returns
+    pub fn index(&self) -> usize {
+        self.idx
+    }
+}
+
+impl<L> RcuGuard<'_> for SrcuGuard<'_, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    fn domain_id(&self) -> u64 {
+        self.srcu.domain_id
+    }
+}
+
+// end the rcu critical section
+impl<L> Drop for SrcuGuard<'_, L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// ends the critical section
+    fn drop(&mut self) {
+        //Ordering: reads in the critical section need to happen before the count is decremented
+        atomic::fence(Ordering::SeqCst);
+        if self.info.ctr[self.idx].fetch_sub(1, Ordering::Relaxed) == 1 {
+            //Ordering: either sync sees us done, or we see it waiting
+            atomic::fence(Ordering::SeqCst);
+            utils::wake_syncer(&self.srcu.gp_futex);
+        }
+    }
+}
+
+/// ctr counts how many read side critical sections this thread has open in each index
+type SrcuEntry = ReaderEntry<[AtomicU32; 2]>;
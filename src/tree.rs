@@ -0,0 +1,362 @@
+use crate::utils::{gp_target, seq_reached, Lock};
+use crate::{new_domain_id, RcuGuard, RcuHandle, RegisterError, SleepingRcu, RCU};
+use std::marker::PhantomData;
+use std::sync::atomic::{self, AtomicU32, AtomicU64, Ordering};
+
+/// threads per leaf, one bit each in a leaf's masks
+const LEAF_FANOUT: usize = 64;
+/// leaves under the root, one bit each in the root's mask
+const ROOT_FANOUT: usize = 64;
+
+/// TreeRcu, QSBR with hierarchical grace period tracking, similar to Linux's tree rcu
+/// threads are grouped into leaves, and report quiescent states by clearing their bit in their
+/// leaf. The last thread of a leaf to report clears the leaf's bit in the root, and whoever
+/// clears the root ends the grace period. So sync only waits on a single futex instead of
+/// visiting every thread, and threads only contend with the other threads of their leaf
+///
+/// like Qsbr threads must call quiescent_state periodically. Supports up to 4096 registered
+/// threads
+#[derive(Debug)]
+pub struct TreeRcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    leaves: Box<[Leaf]>,
+    /// leaves that still have threads that need to report a quiescent state for the current
+    /// grace period
+    root: AtomicU64,
+    /// id of the thread in each slot, only valid for used slots
+    ids: Box<[AtomicU64]>,
+    /// grace period sequence number, odd while a grace period is in progress
+    gp_seq: AtomicU32,
+    /// serializes starting grace periods and changes to the online and used masks
+    lock: L,
+    /// see RCU::domain_id
+    domain_id: u64,
+}
+
+#[derive(Debug, Default)]
+struct Leaf {
+    /// threads that still need to report a quiescent state for the current grace period
+    qsmask: AtomicU64,
+    /// registered threads that aren't sleeping, only changed under the lock
+    online: AtomicU64,
+    /// registered threads, only changed under the lock
+    used: AtomicU64,
+}
+
+impl<L> Default for TreeRcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L> RCU for TreeRcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    type Handle<'a> = TreeThreadHandle<'a, L> where L: 'a;
+    /// create a new TreeRcu
+    fn new() -> Self {
+        Self {
+            leaves: (0..ROOT_FANOUT).map(|_| Leaf::default()).collect(),
+            root: AtomicU64::new(0),
+            ids: (0..ROOT_FANOUT * LEAF_FANOUT)
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            gp_seq: AtomicU32::new(0),
+            lock: L::new(),
+            domain_id: new_domain_id(),
+        }
+    }
+    /// register a new thread with TreeRcu
+    /// takes an unique id for this handle, fails if id is already registered, or if 4096
+    /// threads already are
+    fn register(&self, id: u64) -> Result<Self::Handle<'_>, RegisterError> {
+        let guard = self.lock.lock();
+        let mut free = None;
+        for (l, leaf) in self.leaves.iter().enumerate() {
+            let used = leaf.used.load(Ordering::Relaxed);
+            for b in 0..LEAF_FANOUT {
+                let slot = l * LEAF_FANOUT + b;
+                if used & (1 << b) == 0 {
+                    free.get_or_insert(slot);
+                } else if self.ids[slot].load(Ordering::Relaxed) == id {
+                    return Err(RegisterError::DuplicateId(id));
+                }
+            }
+        }
+        let slot = free.ok_or(RegisterError::Full)?;
+        let (leaf, bit) = (slot / LEAF_FANOUT, 1 << (slot % LEAF_FANOUT));
+        self.ids[slot].store(id, Ordering::Relaxed);
+        self.leaves[leaf].used.fetch_or(bit, Ordering::Relaxed);
+        // not part of a grace period that is already in progress, so only starts reporting from
+        // the next one
+        self.leaves[leaf].online.fetch_or(bit, Ordering::Relaxed);
+        // read under the lock, a grace period started after this one waits on us
+        let seq = self.gp_seq.load(Ordering::Acquire);
+        drop(guard);
+        Ok(TreeThreadHandle {
+            rcu: self,
+            leaf,
+            bit,
+            reported: AtomicU32::new(seq),
+            nesting: AtomicU32::new(0),
+        })
+    }
+    fn domain_id(&self) -> u64 {
+        self.domain_id
+    }
+}
+
+impl<L> TreeRcu<L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// start a grace period waiting on every online thread
+    /// caller must hold self.lock, and seq must be the current, even, gp_seq
+    fn start_grace_period(&self, seq: u32) {
+        // the root is set up before the leaves, so a late report from the last grace period
+        // that empties a leaf can't be lost, see report_qs
+        let root = self
+            .leaves
+            .iter()
+            .enumerate()
+            .filter(|(_, leaf)| leaf.online.load(Ordering::Relaxed) != 0)
+            .fold(0, |root, (l, _)| root | 1 << l);
+        self.root.store(root, Ordering::Relaxed);
+        for leaf in self.leaves.iter() {
+            leaf.qsmask
+                .store(leaf.online.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+        //Ordering: the masks need to be set up before threads see the grace period and report
+        self.gp_seq.store(seq.wrapping_add(1), Ordering::Release);
+        if root == 0 {
+            self.gp_seq.store(seq.wrapping_add(2), Ordering::Release);
+        }
+        atomic_wait::wake_all(&self.gp_seq);
+    }
+
+    /// clear `bit` in `leaf`, propagating up to the root if it was the last one, and ending the
+    /// grace period if the leaf was the last one
+    ///
+    /// must only be called from a quiescent state, reports are not tied to a particular grace
+    /// period, so a late one can clear a bit for a newer grace period, which is fine since the
+    /// thread is quiescent at that point too
+    fn report_qs(&self, leaf: usize, bit: u64) {
+        //Ordering: the critical sections before this quiescent state happen before the grace
+        //period ends, Acquire so whoever ends it sees every report before it
+        let prev = self.leaves[leaf].qsmask.fetch_and(!bit, Ordering::AcqRel);
+        if prev != bit {
+            // other threads in the leaf still need to report, or bit wasn't set
+            return;
+        }
+        let leaf_bit = 1 << leaf;
+        let prev = self.root.fetch_and(!leaf_bit, Ordering::AcqRel);
+        if prev != leaf_bit {
+            return;
+        }
+        let seq = self.gp_seq.load(Ordering::Relaxed);
+        debug_assert!(seq & 1 == 1, "grace period ended twice");
+        self.gp_seq.store(seq.wrapping_add(1), Ordering::Release);
+        atomic_wait::wake_all(&self.gp_seq);
+    }
+}
+
+///created via TreeRcu::register(), used to register a thread with the TreeRcu
+pub struct TreeThreadHandle<'a, L>
+where
+    L: for<'b> Lock<'b>,
+    Self: 'a,
+{
+    rcu: &'a TreeRcu<L>,
+    leaf: usize,
+    /// this thread's bit in its leaf
+    bit: u64,
+    /// last grace period this thread reported a quiescent state for
+    reported: AtomicU32,
+    /// number of live guards from read(), read side critical sections nest
+    nesting: AtomicU32,
+}
+
+impl<L> TreeThreadHandle<'_, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    /// report a quiescent state if there is a grace period this thread hasn't reported one for
+    fn report(&self) {
+        self.check_not_reading("quiescent_state");
+        //Ordering: Acquire so the critical sections after this see everything the grace period
+        //was started for
+        let seq = self.rcu.gp_seq.load(Ordering::Acquire);
+        // swapped so a handle shared between threads only reports once per grace period
+        if seq & 1 == 0 || self.reported.swap(seq, Ordering::Relaxed) == seq {
+            return;
+        }
+        self.rcu.report_qs(self.leaf, self.bit);
+    }
+
+    /// in debug builds, panic if this thread is inside a read side critical section, since
+    /// `op` treats it as quiescent
+    fn check_not_reading(&self, op: &str) {
+        debug_assert!(
+            self.nesting.load(Ordering::Relaxed) == 0,
+            "{} called inside a read side critical section",
+            op
+        );
+    }
+
+    /// take this thread out of grace periods, reporting a quiescent state for the current one
+    fn go_offline(&self) {
+        let guard = self.rcu.lock.lock();
+        self.rcu.leaves[self.leaf]
+            .online
+            .fetch_and(!self.bit, Ordering::Relaxed);
+        self.rcu.report_qs(self.leaf, self.bit);
+        drop(guard);
+    }
+}
+
+impl<'a, L> RcuHandle<'a> for TreeThreadHandle<'a, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    type Guard<'b> = TreeGuard<'b, L> where Self: 'a, 'a: 'b;
+    type Sleeper<'b> = TreeSleeper<'b, L> where Self: 'a, 'a: 'b;
+    /// long quiescent state, grace periods don't wait on sleeping threads
+    fn sleep(self) -> Self::Sleeper<'a> {
+        self.check_not_reading("sleep");
+        self.go_offline();
+        Self::Sleeper { handle: self }
+    }
+    /// read starts an rcu critical section, which lasts until the returned TreeGuard is
+    /// dropped, only bumps a nesting counter like Qsbr
+    fn read(&self) -> Self::Guard<'_> {
+        self.nesting.fetch_add(1, Ordering::Relaxed);
+        TreeGuard {
+            nesting: &self.nesting,
+            domain_id: self.rcu.domain_id,
+            _lock: PhantomData,
+        }
+    }
+    /// signal that this thread has passed through a quiescent state, only touches shared state
+    /// once per grace period
+    fn quiescent_state(&mut self) {
+        self.report();
+    }
+
+    fn quiescent_sync(&mut self) {
+        self.sync();
+    }
+
+    /// blocks until a grace period has passed, concurrent syncs share grace periods like
+    /// Qsbr::sync, and this thread is treated as quiescent while waiting
+    fn sync(&self) {
+        self.check_not_reading("sync");
+        //Ordering: removals before the sync need to be visible to the thread that starts the
+        //grace period we wait on
+        atomic::fence(Ordering::SeqCst);
+        let target = gp_target(self.rcu.gp_seq.load(Ordering::Acquire));
+        loop {
+            let seq = self.rcu.gp_seq.load(Ordering::Acquire);
+            if seq_reached(seq, target) {
+                break;
+            }
+            if seq & 1 == 0 {
+                let guard = self.rcu.lock.lock();
+                if self.rcu.gp_seq.load(Ordering::Relaxed) == seq {
+                    self.rcu.start_grace_period(seq);
+                }
+                drop(guard);
+                continue;
+            }
+            self.report();
+            atomic_wait::wait(&self.rcu.gp_seq, seq);
+        }
+    }
+
+    fn is_reading(&self) -> bool {
+        self.nesting.load(Ordering::Relaxed) != 0
+    }
+
+    fn domain_id(&self) -> u64 {
+        self.rcu.domain_id
+    }
+}
+
+//unregistering a thread
+impl<L> Drop for TreeThreadHandle<'_, L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// unregisters the given handle with TreeRcu, no memory is freed so no need to sync
+    fn drop(&mut self) {
+        let guard = self.rcu.lock.lock();
+        let leaf = &self.rcu.leaves[self.leaf];
+        leaf.online.fetch_and(!self.bit, Ordering::Relaxed);
+        leaf.used.fetch_and(!self.bit, Ordering::Relaxed);
+        self.rcu.report_qs(self.leaf, self.bit);
+        drop(guard);
+    }
+}
+
+pub struct TreeSleeper<'a, L>
+where
+    L: for<'l> Lock<'l>,
+{
+    handle: TreeThreadHandle<'a, L>,
+}
+
+impl<'a, L> SleepingRcu<'a> for TreeSleeper<'a, L>
+where
+    L: for<'l> Lock<'l>,
+{
+    type Handle = TreeThreadHandle<'a, L>;
+    fn wake(self) -> Self::Handle {
+        let rcu = self.handle.rcu;
+        let guard = rcu.lock.lock();
+        rcu.leaves[self.handle.leaf]
+            .online
+            .fetch_or(self.handle.bit, Ordering::Relaxed);
+        drop(guard);
+        //Ordering: critical sections after waking need to see grace periods that started while
+        //asleep
+        atomic::fence(Ordering::SeqCst);
+        self.handle
+    }
+}
+
+/// TreeGuard, like QsbrGuard only tracks nesting, grace periods rely on quiescent_state not
+/// being called while it is alive
+pub struct TreeGuard<'a, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    /// the handle's nesting counter
+    nesting: &'a AtomicU32,
+    domain_id: u64,
+    _lock: PhantomData<L>,
+}
+
+impl<L> RcuGuard<'_> for TreeGuard<'_, L>
+where
+    L: for<'lock> Lock<'lock>,
+{
+    fn domain_id(&self) -> u64 {
+        self.domain_id
+    }
+}
+
+// end the rcu critical section
+impl<L> Drop for TreeGuard<'_, L>
+where
+    L: for<'a> Lock<'a>,
+{
+    /// ends the critical section, if this is the outermost guard
+    fn drop(&mut self) {
+        self.nesting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -1,9 +1,16 @@
+use crate::cds::rculist::RcuList;
+use crate::{RcuHandle, SleepingRcu, RCU};
 use std::sync::atomic::{AtomicU32, Ordering};
 
 pub trait Lock<'a> {
     type Guard;
     fn lock(&'a self) -> Self::Guard;
     fn new() -> Self;
+    /// true if some thread holds the lock, for debug assertions that a writer holds it
+    /// locks that can't tell always return true
+    fn is_locked(&self) -> bool {
+        true
+    }
 }
 
 #[derive(Debug)]
@@ -40,6 +47,10 @@ impl<'a> Lock<'a> for Futex {
         }
         Self::Guard { futex: self }
     }
+
+    fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != 0
+    }
 }
 
 #[derive(Debug)]
@@ -74,4 +85,128 @@ impl<'a> Lock<'a> for SpinLock {
         }
         SpinLockGuard { spin: self }
     }
+
+    fn is_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != 0
+    }
+}
+
+/// entry in the thread list of flavors whose readers track their own critical sections, like
+/// Mb and Srcu, ordered by id so registering can reject duplicates
+#[derive(Debug)]
+pub(crate) struct ReaderEntry<C> {
+    /// the flavor's per thread reader counter
+    pub(crate) ctr: C,
+    /// this thread's id, needs to be unique for each entry
+    pub(crate) id: u64,
+}
+
+impl<C> ReaderEntry<C> {
+    pub(crate) fn new(id: u64, ctr: C) -> Self {
+        ReaderEntry { ctr, id }
+    }
+}
+
+impl<C> PartialEq for ReaderEntry<C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<C> PartialOrd for ReaderEntry<C> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.id.cmp(&other.id))
+    }
+}
+
+/// flip the grace period phase with `flip`, then wait for every reader still in the old phase
+/// to leave its critical section, syncs call it twice since a reader could have loaded the old
+/// phase just before the first flip but not stored it until after it was checked
+/// `flip` returns what `in_old_phase` needs to check a reader's counter against, `barrier` is
+/// the full fence pairing with the readers', and readers wake `gp_futex` with wake_syncer
+///
+/// # Safety
+///
+/// no entry can be removed from `threads` while this runs, e.g. by holding the lock removers
+/// take
+pub(crate) unsafe fn flip_and_wait<C, P, R, L>(
+    threads: &RcuList<ReaderEntry<C>, R, L>,
+    gp_futex: &AtomicU32,
+    barrier: impl Fn(),
+    flip: impl FnOnce() -> P,
+    in_old_phase: impl Fn(&C, &P) -> bool,
+) where
+    R: RCU,
+    L: for<'a> Lock<'a>,
+{
+    let phase = flip();
+    //Ordering: readers starting after this must see the new phase
+    barrier();
+    loop {
+        gp_futex.store(1, Ordering::Relaxed);
+        //Ordering: either we see the readers as done, or the last one sees gp_futex set and
+        //wakes us
+        barrier();
+        // Saftey: see above
+        let active = unsafe { threads.iter_unguarded() }.any(|e| in_old_phase(&e.ctr, &phase));
+        if !active {
+            gp_futex.store(0, Ordering::Relaxed);
+            return;
+        }
+        atomic_wait::wait(gp_futex, 1);
+    }
+}
+
+/// called by a reader leaving its outermost critical section after the barrier pairing with
+/// flip_and_wait, wakes a sync waiting on it
+pub(crate) fn wake_syncer(gp_futex: &AtomicU32) {
+    if gp_futex.load(Ordering::Relaxed) != 0 {
+        gp_futex.store(0, Ordering::Relaxed);
+        atomic_wait::wake_all(gp_futex);
+    }
+}
+
+/// remove and free `entry`, for flavors whose syncs hold `lock` while walking `threads`
+pub(crate) fn unregister<C, R, L>(
+    threads: &RcuList<ReaderEntry<C>, R, L>,
+    lock: &L,
+    entry: &ReaderEntry<C>,
+) where
+    R: RCU,
+    L: for<'a> Lock<'a>,
+{
+    // only sync traverses the list, and it holds the lock while doing so
+    let guard = lock.lock();
+    let entry_ptr = unsafe { threads.remove_unsynced(entry) }.expect("thread is not registered");
+    // Saftey no sync is running, and we removed the entry so no future sync will see it
+    let _ = unsafe { Box::from_raw(entry_ptr) };
+    drop(guard);
+}
+
+/// the grace period sequence number a sync that read `seq` has to wait for, with sequence
+/// numbers that are odd while a grace period is in progress. A grace period already in
+/// progress may have started before the sync's removals, so it waits for the one after it
+pub(crate) fn gp_target(seq: u32) -> u32 {
+    seq.wrapping_add(3) & !1
+}
+
+/// wrapping compare, seq >= target
+pub(crate) fn seq_reached(seq: u32, target: u32) -> bool {
+    seq.wrapping_sub(target) as i32 >= 0
+}
+
+/// sleeping handle of flavors whose readers are only active inside a critical section, so
+/// sleeping is a no op
+pub struct Sleeper<H> {
+    pub(crate) handle: H,
+}
+
+impl<'a, H> SleepingRcu<'a> for Sleeper<H>
+where
+    H: RcuHandle<'a>,
+{
+    type Handle = H;
+    fn wake(self) -> Self::Handle {
+        self.handle
+    }
 }
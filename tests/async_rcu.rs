@@ -0,0 +1,185 @@
+use rcu::async_rcu::AsyncRcu;
+use rcu::utils::{Futex, SpinLock};
+use rcu::{RcuHandle, RCU};
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::task::{Context, Poll, Wake};
+use std::thread::{self, Thread};
+use std::time::Duration;
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let waker = Arc::new(ThreadWaker(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+            return out;
+        }
+        thread::park();
+    }
+}
+
+/// counts how many times it is woken
+struct CountingWaker(AtomicUsize);
+
+impl Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// yields once before completing
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+    fn poll(mut self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[test]
+fn single_threaded_synchronize() {
+    let my_rcu = AsyncRcu::<Futex>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    block_on(t_handle.synchronize());
+}
+
+#[test]
+fn synchronize_waits_for_quiescent_state() {
+    let my_rcu = AsyncRcu::<SpinLock>::new();
+    let synced = AtomicBool::new(false);
+    let registered = Barrier::new(2);
+    thread::scope(|s| {
+        let mut worker = my_rcu.register(1).unwrap();
+        s.spawn(|| {
+            let writer = my_rcu.register(2).unwrap();
+            registered.wait();
+            block_on(writer.synchronize());
+            synced.store(true, Ordering::Release);
+        });
+        registered.wait();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!synced.load(Ordering::Acquire));
+        worker.quiescent_state();
+    });
+    assert!(synced.load(Ordering::Acquire));
+}
+
+#[test]
+fn yielding_is_quiescent() {
+    let my_rcu = AsyncRcu::<Futex>::new();
+    let registered = Barrier::new(2);
+    let synced = AtomicBool::new(false);
+    thread::scope(|s| {
+        let worker = my_rcu.register(1).unwrap();
+        s.spawn(|| {
+            let writer = my_rcu.register(2).unwrap();
+            registered.wait();
+            block_on(writer.synchronize());
+            synced.store(true, Ordering::Release);
+        });
+        registered.wait();
+        while !synced.load(Ordering::Acquire) {
+            block_on(worker.quiescent(YieldNow(false)));
+        }
+    });
+}
+
+#[test]
+fn repolling_synchronize_keeps_one_waker() {
+    let my_rcu = AsyncRcu::<Futex>::new();
+    let mut worker = my_rcu.register(1).unwrap();
+    let writer = my_rcu.register(2).unwrap();
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = counter.clone().into();
+    let mut cx = Context::from_waker(&waker);
+    {
+        let mut sync = pin!(writer.synchronize());
+        for _ in 0..100 {
+            assert!(sync.as_mut().poll(&mut cx).is_pending());
+        }
+        worker.quiescent_state();
+        assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+        assert!(sync.as_mut().poll(&mut cx).is_ready());
+    }
+    // both handles are on this thread, so one must be asleep while the other drops
+    let writer = writer.sleep();
+    drop(worker);
+    drop(writer);
+}
+
+#[test]
+fn futures_are_send() {
+    fn assert_send<T: Send>(_: &T) {}
+
+    let my_rcu = AsyncRcu::<Futex>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    assert_send(&t_handle.synchronize());
+    assert_send(&t_handle.quiescent(async {}));
+}
+
+#[test]
+fn quiescent_synchronize_doesnt_wake_itself() {
+    let my_rcu = AsyncRcu::<Futex>::new();
+    let mut worker = my_rcu.register(1).unwrap();
+    let writer = my_rcu.register(2).unwrap();
+    let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+    let waker = counter.clone().into();
+    let mut cx = Context::from_waker(&waker);
+    {
+        let mut sync = pin!(writer.quiescent(writer.synchronize()));
+        for _ in 0..5 {
+            assert!(sync.as_mut().poll(&mut cx).is_pending());
+        }
+        assert_eq!(counter.0.load(Ordering::Relaxed), 0);
+        worker.quiescent_state();
+        assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+        assert!(sync.as_mut().poll(&mut cx).is_ready());
+    }
+    let writer = writer.sleep();
+    drop(worker);
+    drop(writer);
+}
+
+#[test]
+fn quiescent_skips_report_while_reading() {
+    let my_rcu = AsyncRcu::<Futex>::new();
+    let writer = my_rcu.register(1).unwrap();
+    let reader = my_rcu.register(2).unwrap();
+    let waker = Arc::new(CountingWaker(AtomicUsize::new(0))).into();
+    let mut cx = Context::from_waker(&waker);
+    {
+        let mut sync = pin!(writer.synchronize());
+        assert!(sync.as_mut().poll(&mut cx).is_pending());
+        {
+            let guard = reader.read();
+            let mut pending = pin!(reader.quiescent(std::future::pending::<()>()));
+            assert!(pending.as_mut().poll(&mut cx).is_pending());
+            drop(guard);
+        }
+        // the reader was inside a critical section, so it didn't report a quiescent state
+        assert!(sync.as_mut().poll(&mut cx).is_pending());
+        let mut pending = pin!(reader.quiescent(std::future::pending::<()>()));
+        assert!(pending.as_mut().poll(&mut cx).is_pending());
+        assert!(sync.as_mut().poll(&mut cx).is_ready());
+    }
+    let writer = writer.sleep();
+    drop(reader);
+    drop(writer);
+}
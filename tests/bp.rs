@@ -0,0 +1,37 @@
+use rcu::cds::rculist::{RcuList, RcuListIterator};
+use rcu::utils::Futex;
+use rcu::{bp, mb::Mb, qsbr::Qsbr, RcuHandle, RCU};
+use std::thread;
+
+#[test]
+fn nested_reads_register_once() {
+    let my_rcu: &'static Mb<Futex> = Box::leak(Box::new(Mb::new()));
+    let list: &'static RcuList<u32, Mb<Futex>, Futex> = Box::leak(Box::new(RcuList::new()));
+    list.insert(1);
+    bp::read(my_rcu, |outer| {
+        bp::read(my_rcu, |inner| {
+            assert_eq!(RcuListIterator::new(inner, list).count(), 1);
+        });
+        assert_eq!(RcuListIterator::new(outer, list).count(), 1);
+    });
+    bp::with_handle(my_rcu, |handle| handle.sync());
+}
+
+#[test]
+fn threads_unregister_on_exit() {
+    let my_rcu: &'static Qsbr<Futex> = Box::leak(Box::new(Qsbr::new()));
+    let list: &'static RcuList<u32, Qsbr<Futex>, Futex> = Box::leak(Box::new(RcuList::new()));
+    thread::scope(|s| {
+        for i in 0..10 {
+            s.spawn(move || {
+                list.insert(i);
+                bp::read(my_rcu, |guard| {
+                    assert!(RcuListIterator::new(guard, list).any(|e| *e == i));
+                });
+            });
+        }
+    });
+    // would block forever if any of the exited threads were still registered
+    let removed = bp::with_handle(my_rcu, |handle| list.remove(&3, handle));
+    assert_eq!(removed, Some(3));
+}
@@ -0,0 +1,199 @@
+//! memory model tests, run with
+//! RUSTFLAGS="--cfg loom" cargo test --release --test loom
+#![cfg(loom)]
+
+use loom::cell::UnsafeCell;
+use loom::sync::Arc;
+use loom::thread;
+use rcu::cds::rculist::{RcuList, RcuListIterator};
+use rcu::utils::Futex;
+use rcu::{RcuGuard, RcuHandle, RegisterError, SleepingRcu, RCU};
+use std::marker::PhantomData;
+
+/// rcu flavor that does nothing, these tests never free elements so only the memory ordering of
+/// publishing them matters, and real flavors block on futexes loom can't model
+struct LoomRcu;
+
+struct LoomHandle<'a>(PhantomData<&'a LoomRcu>);
+
+struct LoomGuard;
+
+impl RCU for LoomRcu {
+    type Handle<'a> = LoomHandle<'a>;
+    fn new() -> Self {
+        LoomRcu
+    }
+    fn register(&self, _id: u64) -> Result<Self::Handle<'_>, RegisterError> {
+        Ok(LoomHandle(PhantomData))
+    }
+    fn domain_id(&self) -> u64 {
+        1
+    }
+}
+
+impl<'a> RcuHandle<'a> for LoomHandle<'a> {
+    type Guard<'g>
+        = LoomGuard
+    where
+        'a: 'g;
+    type Sleeper<'s>
+        = LoomHandle<'s>
+    where
+        'a: 's;
+    fn read(&self) -> Self::Guard<'_> {
+        LoomGuard
+    }
+    fn quiescent_state(&mut self) {}
+    fn sleep(self) -> Self::Sleeper<'a> {
+        self
+    }
+    fn sync(&self) {}
+    fn quiescent_sync(&mut self) {}
+    fn is_reading(&self) -> bool {
+        false
+    }
+    fn domain_id(&self) -> u64 {
+        1
+    }
+}
+
+impl<'a> SleepingRcu<'a> for LoomHandle<'a> {
+    type Handle = LoomHandle<'a>;
+    fn wake(self) -> Self::Handle {
+        self
+    }
+}
+
+impl RcuGuard<'_> for LoomGuard {
+    fn domain_id(&self) -> u64 {
+        1
+    }
+}
+
+/// list element with a value loom tracks, reading it before its initialization is visible is
+/// reported as a causality violation
+struct Node {
+    key: u32,
+    val: UnsafeCell<u32>,
+}
+
+impl Node {
+    fn new(key: u32) -> Self {
+        Node {
+            key,
+            val: UnsafeCell::new(key * 10),
+        }
+    }
+
+    fn val(&self) -> u32 {
+        self.val.with(|v| unsafe { *v })
+    }
+}
+
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.key.partial_cmp(&other.key)
+    }
+}
+
+type List = RcuList<Node, LoomRcu, Futex>;
+
+/// walk the list, checking every element it reaches is fully initialized and in order
+fn check_list(list: &List) -> Vec<u32> {
+    let rcu = LoomRcu;
+    let handle = rcu.register(1).unwrap();
+    let guard = handle.read();
+    let keys: Vec<_> = RcuListIterator::new(&guard, list)
+        .map(|n| {
+            assert_eq!(n.val(), n.key * 10);
+            n.key
+        })
+        .collect();
+    assert!(keys.windows(2).all(|w| w[0] < w[1]));
+    keys
+}
+
+#[test]
+fn insert_into_empty_list_is_published() {
+    loom::model(|| {
+        let list = Arc::new(List::new());
+        let reader = {
+            let list = list.clone();
+            thread::spawn(move || check_list(&list))
+        };
+        list.insert(Node::new(1));
+        let keys = reader.join().unwrap();
+        assert!(keys.is_empty() || keys == [1]);
+    });
+}
+
+#[test]
+fn insert_between_elements_is_published() {
+    loom::model(|| {
+        let list = Arc::new(List::new());
+        list.insert(Node::new(1));
+        list.insert(Node::new(3));
+        let reader = {
+            let list = list.clone();
+            thread::spawn(move || check_list(&list))
+        };
+        list.insert(Node::new(2));
+        list.insert(Node::new(0));
+        let keys = reader.join().unwrap();
+        assert!(keys.contains(&1) && keys.contains(&3));
+    });
+}
+
+#[test]
+fn unlink_keeps_remaining_elements_reachable() {
+    loom::model(|| {
+        let list = Arc::new(List::new());
+        list.insert(Node::new(1));
+        let reader = {
+            let list = list.clone();
+            thread::spawn(move || check_list(&list))
+        };
+        list.insert(Node::new(2));
+        list.insert(Node::new(3));
+        // Saftey: the reader may still be referencing it, so it is only freed after joining
+        let removed = unsafe { list.remove_unsynced(&Node::new(2)) }.unwrap();
+        let keys = reader.join().unwrap();
+        let _ = unsafe { Box::from_raw(removed) };
+        assert!(keys.contains(&1));
+    });
+}
+
+#[test]
+fn reverse_walk_sees_published_elements() {
+    loom::model(|| {
+        let list = Arc::new(List::new());
+        list.insert(Node::new(2));
+        let reader = {
+            let list = list.clone();
+            thread::spawn(move || {
+                let rcu = LoomRcu;
+                let handle = rcu.register(1).unwrap();
+                let guard = handle.read();
+                let keys: Vec<_> = RcuListIterator::new(&guard, &list)
+                    .rev()
+                    .map(|n| {
+                        assert_eq!(n.val(), n.key * 10);
+                        n.key
+                    })
+                    .collect();
+                assert!(keys.windows(2).all(|w| w[0] > w[1]));
+                keys
+            })
+        };
+        list.insert(Node::new(3));
+        list.insert(Node::new(1));
+        let keys = reader.join().unwrap();
+        assert!(keys.contains(&2));
+    });
+}
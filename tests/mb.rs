@@ -0,0 +1,103 @@
+use rcu::mb::Mb;
+use rcu::utils::{Futex, Lock, SpinLock};
+use rcu::{RcuHandle, RCU};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+fn register_worker<L>(id: u64, rcu_handle: &Mb<L>)
+where
+    L: for<'a> Lock<'a>,
+{
+    let t_handle = rcu_handle.register(id).unwrap();
+    let _guard = t_handle.read();
+}
+
+#[test]
+fn single_threaded_register_futex() {
+    let my_rcu = Mb::<Futex>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    t_handle.sync();
+}
+
+#[test]
+fn multi_threaded_register_spin() {
+    let my_rcu = Mb::<SpinLock>::new();
+    thread::scope(|s| {
+        for i in 0..20 {
+            let handle = &my_rcu;
+            s.spawn(move || {
+                register_worker(i, handle);
+                register_worker(i, handle);
+            });
+        }
+    });
+}
+
+#[test]
+fn nested_reads() {
+    let my_rcu = Mb::<Futex>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    let outer = t_handle.read();
+    let inner = t_handle.read();
+    drop(inner);
+    drop(outer);
+    t_handle.sync();
+}
+
+#[test]
+fn sync_waits_for_reader() {
+    let my_rcu = Mb::<Futex>::new();
+    let reading = AtomicBool::new(false);
+    let synced = AtomicBool::new(false);
+    thread::scope(|s| {
+        let reader = my_rcu.register(1).unwrap();
+        let guard = reader.read();
+        s.spawn(|| {
+            let writer = my_rcu.register(2).unwrap();
+            while !reading.load(Ordering::Acquire) {
+                std::hint::spin_loop();
+            }
+            writer.sync();
+            synced.store(true, Ordering::Release);
+        });
+        reading.store(true, Ordering::Release);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!synced.load(Ordering::Acquire));
+        drop(guard);
+    });
+    assert!(synced.load(Ordering::Acquire));
+}
+
+#[test]
+fn mb_is_reading() {
+    let my_rcu = Mb::<Futex>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    assert!(!t_handle.is_reading());
+    let outer = t_handle.read();
+    let inner = t_handle.read();
+    drop(inner);
+    assert!(t_handle.is_reading());
+    drop(outer);
+    assert!(!t_handle.is_reading());
+}
+
+#[test]
+fn shared_handle_reads() {
+    let my_rcu = Mb::<Futex>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..10000 {
+                    let outer = t_handle.read();
+                    let inner = t_handle.read();
+                    drop(inner);
+                    drop(outer);
+                }
+            });
+        }
+    });
+    assert!(!t_handle.is_reading());
+    t_handle.sync();
+}
@@ -0,0 +1,52 @@
+use rcu::memb::Memb;
+use rcu::utils::{Futex, SpinLock};
+use rcu::{RcuHandle, RCU};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn single_threaded_register_futex() {
+    let my_rcu = Memb::<Futex>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    drop(t_handle.read());
+    t_handle.sync();
+}
+
+#[test]
+fn multi_threaded_register_spin() {
+    let my_rcu = Memb::<SpinLock>::new();
+    thread::scope(|s| {
+        for i in 0..20 {
+            let handle = &my_rcu;
+            s.spawn(move || {
+                let t_handle = handle.register(i).unwrap();
+                let _guard = t_handle.read();
+            });
+        }
+    });
+}
+
+#[test]
+fn sync_waits_for_reader() {
+    let my_rcu = Memb::<Futex>::new();
+    let reading = AtomicBool::new(false);
+    let synced = AtomicBool::new(false);
+    thread::scope(|s| {
+        let reader = my_rcu.register(1).unwrap();
+        let guard = reader.read();
+        s.spawn(|| {
+            let writer = my_rcu.register(2).unwrap();
+            while !reading.load(Ordering::Acquire) {
+                std::hint::spin_loop();
+            }
+            writer.sync();
+            synced.store(true, Ordering::Release);
+        });
+        reading.store(true, Ordering::Release);
+        thread::sleep(Duration::from_millis(50));
+        assert!(!synced.load(Ordering::Acquire));
+        drop(guard);
+    });
+    assert!(synced.load(Ordering::Acquire));
+}
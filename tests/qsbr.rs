@@ -7,7 +7,7 @@ fn register_worker<L>(id: u64, rcu_handle: &Qsbr<L>)
 where
     L: for<'a> Lock<'a>,
 {
-    let mut _t_handle = rcu_handle.register(id);
+    let mut _t_handle = rcu_handle.register(id).unwrap();
 }
 
 #[test]
@@ -24,7 +24,7 @@ fn can_make_qsbr_spin() {
 fn single_threaded_register_futex() {
     let my_rcu = Qsbr::<Futex>::new();
     println!("{:?}", my_rcu);
-    let _t_handle = my_rcu.register(1);
+    let _t_handle = my_rcu.register(1).unwrap();
     println!("{:?}", my_rcu);
 }
 
@@ -32,7 +32,7 @@ fn single_threaded_register_futex() {
 fn single_threaded_register_spin() {
     let my_rcu = Qsbr::<SpinLock>::new();
     println!("{:?}", my_rcu);
-    let _t_handle = my_rcu.register(1);
+    let _t_handle = my_rcu.register(1).unwrap();
     println!("{:?}", my_rcu);
 }
 
@@ -87,3 +87,527 @@ fn multi_threaded_register_spin() {
         }
     });
 }
+
+#[test]
+fn call_rcu_waits_for_grace_period() {
+    use rcu::RcuHandle;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Barrier};
+
+    let my_rcu = Qsbr::<Futex>::new();
+    let ran = Arc::new(AtomicBool::new(false));
+    let queued = Barrier::new(2);
+    let checked = Barrier::new(2);
+    thread::scope(|s| {
+        let mut reader = my_rcu.register(1).unwrap();
+        s.spawn(|| {
+            let mut writer = my_rcu.register(2).unwrap();
+            let queued_ran = ran.clone();
+            writer.call_rcu(move || queued_ran.store(true, Ordering::Relaxed));
+            writer.quiescent_state();
+            queued.wait();
+            // reader hasn't passed through a quiescent state yet
+            assert!(!ran.load(Ordering::Relaxed));
+            checked.wait();
+            checked.wait();
+            writer.quiescent_state();
+            assert!(ran.load(Ordering::Relaxed));
+        });
+        queued.wait();
+        checked.wait();
+        reader.quiescent_state();
+        checked.wait();
+    });
+}
+
+#[test]
+fn defer_drop_runs_on_handle_drop() {
+    use std::sync::Arc;
+
+    let my_rcu = Qsbr::<SpinLock>::new();
+    let value = Arc::new(0);
+    let mut t_handle = my_rcu.register(1).unwrap();
+    t_handle.defer_drop(value.clone());
+    assert_eq!(Arc::strong_count(&value), 2);
+    drop(t_handle);
+    assert_eq!(Arc::strong_count(&value), 1);
+}
+
+#[test]
+fn polled_grace_period() {
+    use rcu::RcuHandle;
+    use std::sync::Barrier;
+
+    let my_rcu = Qsbr::<Futex>::new();
+    let started = Barrier::new(2);
+    let polled = Barrier::new(2);
+    thread::scope(|s| {
+        let mut reader = my_rcu.register(1).unwrap();
+        s.spawn(|| {
+            let mut writer = my_rcu.register(2).unwrap();
+            let cookie = writer.start_grace_period();
+            assert!(!writer.try_sync());
+            started.wait();
+            polled.wait();
+            assert!(!writer.poll_grace_period(&cookie));
+            assert!(!writer.try_sync());
+            polled.wait();
+            polled.wait();
+            assert!(writer.poll_grace_period(&cookie));
+            assert!(writer.try_sync());
+        });
+        started.wait();
+        polled.wait();
+        polled.wait();
+        reader.quiescent_state();
+        polled.wait();
+    });
+}
+
+#[test]
+fn single_threaded_try_sync() {
+    let my_rcu = Qsbr::<SpinLock>::new();
+    let mut t_handle = my_rcu.register(1).unwrap();
+    let cookie = t_handle.start_grace_period();
+    assert!(t_handle.poll_grace_period(&cookie));
+    assert!(t_handle.try_sync());
+}
+
+#[test]
+#[should_panic(expected = "grace period cookie is from a different rcu instance")]
+fn foreign_grace_period_cookie_panics() {
+    let my_rcu = Qsbr::<SpinLock>::new();
+    let other_rcu = Qsbr::<SpinLock>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    let other_handle = other_rcu.register(1).unwrap();
+    let cookie = other_handle.start_grace_period();
+    t_handle.poll_grace_period(&cookie);
+}
+
+#[test]
+fn sync_timeout_reports_stalled_threads() {
+    use rcu::RcuHandle;
+    use std::time::Duration;
+
+    let my_rcu = Qsbr::<Futex>::new();
+    let stuck = my_rcu.register(3).unwrap();
+    let writer = my_rcu.register(5).unwrap();
+    let sleeping = my_rcu.register(4).unwrap().sleep();
+    let err = writer.sync_timeout(Duration::from_millis(10)).unwrap_err();
+    assert_eq!(err.stalled, vec![3]);
+    // every handle on this thread has to be asleep, otherwise unregistering waits on them
+    let stuck = stuck.sleep();
+    assert!(writer.sync_timeout(Duration::from_millis(10)).is_ok());
+    let writer = writer.sleep();
+    drop(sleeping);
+    drop(stuck);
+    drop(writer);
+}
+
+#[test]
+fn concurrent_sync_timeouts_dont_stall_each_other() {
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    let my_rcu = Qsbr::<Futex>::new();
+    let registered = Barrier::new(2);
+    thread::scope(|s| {
+        for id in 1..=2 {
+            let (my_rcu, registered) = (&my_rcu, &registered);
+            s.spawn(move || {
+                let writer = my_rcu.register(id).unwrap();
+                registered.wait();
+                for _ in 0..10 {
+                    assert!(writer.sync_timeout(Duration::from_millis(200)).is_ok());
+                }
+            });
+        }
+    });
+}
+
+struct CaptureLogger(std::sync::Mutex<Vec<String>>);
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+    fn log(&self, record: &log::Record) {
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+    fn flush(&self) {}
+}
+
+static LOGGER: CaptureLogger = CaptureLogger(std::sync::Mutex::new(Vec::new()));
+
+/// install LOGGER, tests using it run concurrently so they filter for their own thread ids
+fn capture_logs() -> &'static CaptureLogger {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Warn);
+    });
+    &LOGGER
+}
+
+#[test]
+fn stall_watchdog_warns() {
+    use rcu::RcuHandle;
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    let logger = capture_logs();
+    let my_rcu = Qsbr::<Futex>::new();
+    my_rcu.set_stall_timeout(Some(Duration::from_millis(5)));
+    let registered = Barrier::new(2);
+    thread::scope(|s| {
+        let mut stuck = my_rcu.register(42).unwrap();
+        s.spawn(|| {
+            let mut writer = my_rcu.register(7).unwrap();
+            registered.wait();
+            writer.quiescent_sync();
+        });
+        registered.wait();
+        thread::sleep(Duration::from_millis(50));
+        stuck.quiescent_state();
+    });
+    let logs = logger.0.lock().unwrap();
+    let logs: Vec<_> = logs
+        .iter()
+        .filter(|l| l.contains("thread 7 is waiting"))
+        .collect();
+    assert!(!logs.is_empty());
+    assert!(logs.iter().all(|l| l.contains("thread 42 ")));
+}
+
+#[test]
+fn stall_watchdog_drop_waits_for_syncers() {
+    use rcu::RcuHandle;
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    let logger = capture_logs();
+    let my_rcu = Qsbr::<Futex>::new();
+    my_rcu.set_stall_timeout(Some(Duration::from_millis(5)));
+    let registered = Barrier::new(3);
+    thread::scope(|s| {
+        let mut stuck = my_rcu.register(201).unwrap();
+        s.spawn(|| {
+            let syncer = my_rcu.register(200).unwrap();
+            registered.wait();
+            syncer.sync();
+        });
+        s.spawn(|| {
+            let dropper = my_rcu.register(202).unwrap();
+            registered.wait();
+            // let the syncer start waiting on the stuck thread
+            thread::sleep(Duration::from_millis(10));
+            // the syncer may still be walking the thread list, so this has to wait for it
+            drop(dropper);
+        });
+        registered.wait();
+        thread::sleep(Duration::from_millis(50));
+        stuck.quiescent_state();
+    });
+    let logs = logger.0.lock().unwrap();
+    assert!(logs
+        .iter()
+        .any(|l| l.contains("thread 200 ") && l.contains("thread 202 is waiting")));
+}
+
+#[test]
+fn duplicate_register_fails() {
+    use rcu::RegisterError;
+
+    let my_rcu = Qsbr::<Futex>::new();
+    let t_handle = my_rcu.register(3).unwrap();
+    assert_eq!(
+        my_rcu.register(3).err(),
+        Some(RegisterError::DuplicateId(3))
+    );
+    drop(t_handle);
+    let _t_handle = my_rcu.register(3).unwrap();
+}
+
+#[test]
+fn concurrent_syncs_share_grace_periods() {
+    use rcu::RcuHandle;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    const WRITERS: u64 = 8;
+    let my_rcu = Qsbr::<Futex>::new();
+    let registered = Barrier::new(WRITERS as usize + 1);
+    let done = AtomicUsize::new(0);
+    thread::scope(|s| {
+        let mut stuck = my_rcu.register(0).unwrap();
+        for id in 1..=WRITERS {
+            let (my_rcu, registered, done) = (&my_rcu, &registered, &done);
+            s.spawn(move || {
+                let writer = my_rcu.register(id).unwrap();
+                registered.wait();
+                writer.sync();
+                done.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        registered.wait();
+        // every writer is blocked on this thread by now
+        thread::sleep(Duration::from_millis(50));
+        while done.load(Ordering::Relaxed) < WRITERS as usize {
+            stuck.quiescent_state();
+            thread::sleep(Duration::from_millis(1));
+        }
+        // one grace period in flight when the rest arrived, plus the one they shared
+        assert!(my_rcu.grace_periods() <= 2);
+        let _sleeper = stuck.sleep();
+    });
+}
+
+/// sets its flag when dropped
+struct DropFlag<'a>(&'a std::sync::atomic::AtomicBool);
+
+impl Drop for DropFlag<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, std::sync::atomic::Ordering::Release);
+    }
+}
+
+#[test]
+fn reclaimer_waits_for_grace_period() {
+    use rcu::RcuHandle;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+    let my_rcu = Qsbr::<Futex>::new();
+    my_rcu.start_reclaimer();
+    let mut reader = my_rcu.register(1).unwrap();
+    my_rcu.defer_free(Box::new(DropFlag(&DROPPED)));
+    thread::sleep(Duration::from_millis(20));
+    assert!(!DROPPED.load(Ordering::Acquire));
+    reader.quiescent_state();
+    while !DROPPED.load(Ordering::Acquire) {
+        thread::sleep(Duration::from_millis(1));
+    }
+    my_rcu.stop_reclaimer();
+}
+
+#[test]
+fn reclaimer_flushes_on_drop() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static STOPPED: AtomicBool = AtomicBool::new(false);
+    static NEVER_STARTED: AtomicBool = AtomicBool::new(false);
+    let my_rcu = Qsbr::<Futex>::new();
+    let reader = my_rcu.register(1).unwrap();
+    my_rcu.start_reclaimer();
+    my_rcu.defer_free(Box::new(DropFlag(&STOPPED)));
+    // the reader never passes through a quiescent state, so stopping can't wait for one
+    my_rcu.stop_reclaimer();
+    assert!(!STOPPED.load(Ordering::Acquire));
+    drop(reader);
+    drop(my_rcu);
+    assert!(STOPPED.load(Ordering::Acquire));
+
+    let my_rcu = Qsbr::<Futex>::new();
+    my_rcu.defer_free(Box::new(DropFlag(&NEVER_STARTED)));
+    drop(my_rcu);
+    assert!(NEVER_STARTED.load(Ordering::Acquire));
+}
+
+#[test]
+fn barrier_runs_queued_callbacks() {
+    use rcu::RcuHandle;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    static FREED: AtomicBool = AtomicBool::new(false);
+    static RAN: AtomicUsize = AtomicUsize::new(0);
+    let my_rcu = Qsbr::<Futex>::new();
+    my_rcu.defer_free(Box::new(DropFlag(&FREED)));
+    let mut writer = my_rcu.register(1).unwrap();
+    for _ in 0..3 {
+        writer.call_rcu(|| {
+            RAN.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    // one batch waiting on a grace period, one still pending
+    writer.quiescent_state();
+    writer.call_rcu(|| {
+        RAN.fetch_add(1, Ordering::Relaxed);
+    });
+    let sleeper = writer.sleep();
+    thread::scope(|s| {
+        s.spawn(|| my_rcu.barrier());
+    });
+    assert_eq!(RAN.load(Ordering::Relaxed), 4);
+    assert!(FREED.load(Ordering::Acquire));
+    drop(sleeper);
+}
+
+#[test]
+fn barrier_waits_for_callbacks_being_flushed() {
+    use rcu::RcuHandle;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Barrier;
+    use std::time::Duration;
+
+    static RAN: AtomicBool = AtomicBool::new(false);
+    let my_rcu = Qsbr::<Futex>::new();
+    let registered = Barrier::new(2);
+    thread::scope(|s| {
+        let mut reader = my_rcu.register(1).unwrap();
+        s.spawn(|| {
+            let mut writer = my_rcu.register(2).unwrap();
+            writer.call_rcu(|| RAN.store(true, Ordering::Relaxed));
+            registered.wait();
+            // takes the callback out, then waits on the reader
+            writer.flush_deferred();
+        });
+        registered.wait();
+        let barrier = s.spawn(|| {
+            thread::sleep(Duration::from_millis(10));
+            my_rcu.barrier();
+            assert!(RAN.load(Ordering::Relaxed));
+        });
+        thread::sleep(Duration::from_millis(30));
+        assert!(!barrier.is_finished());
+        reader.quiescent_state();
+        let reader = reader.sleep();
+        barrier.join().unwrap();
+        drop(reader);
+    });
+}
+
+#[test]
+fn panicking_callback_on_drop_unregisters_handle() {
+    use std::time::Duration;
+
+    let my_rcu = Qsbr::<Futex>::new();
+    let mut t_handle = my_rcu.register(1).unwrap();
+    t_handle.call_rcu(|| panic!("callback panicked"));
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(t_handle)));
+    assert!(result.is_err());
+    // the entry was unregistered, so its id is free and nothing waits on it
+    let t_handle = my_rcu.register(1).unwrap();
+    assert!(t_handle.sync_timeout(Duration::from_millis(100)).is_ok());
+}
+
+#[test]
+fn panicking_callback_leaves_handle_usable() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let my_rcu = Qsbr::<Futex>::new();
+    let mut t_handle = my_rcu.register(1).unwrap();
+    t_handle.call_rcu(|| panic!("callback panicked"));
+    let result =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| t_handle.flush_deferred()));
+    assert!(result.is_err());
+    let ran = Arc::new(AtomicBool::new(false));
+    let queued_ran = ran.clone();
+    t_handle.call_rcu(move || queued_ran.store(true, Ordering::Relaxed));
+    t_handle.flush_deferred();
+    assert!(ran.load(Ordering::Relaxed));
+}
+
+#[test]
+fn wait_strategy_round_trips() {
+    use rcu::qsbr::WaitStrategy;
+
+    let my_rcu = Qsbr::<Futex>::new();
+    for strategy in [
+        WaitStrategy::Spin,
+        WaitStrategy::SpinThenFutex(0),
+        WaitStrategy::SpinThenFutex(1),
+        WaitStrategy::SpinThenFutex(u32::MAX),
+        WaitStrategy::Futex,
+    ] {
+        my_rcu.set_wait_strategy(strategy);
+        assert_eq!(my_rcu.wait_strategy(), strategy);
+    }
+}
+
+#[test]
+fn wait_strategies() {
+    use rcu::qsbr::WaitStrategy;
+    use rcu::RcuHandle;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    for strategy in [
+        WaitStrategy::Spin,
+        WaitStrategy::SpinThenFutex(100),
+        WaitStrategy::Futex,
+    ] {
+        let my_rcu = Qsbr::<Futex>::new();
+        assert_eq!(my_rcu.wait_strategy(), WaitStrategy::Futex);
+        my_rcu.set_wait_strategy(strategy);
+        assert_eq!(my_rcu.wait_strategy(), strategy);
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut reader = my_rcu.register(1).unwrap();
+                while !done.load(Ordering::Relaxed) {
+                    reader.quiescent_state();
+                }
+            });
+            let mut writer = my_rcu.register(2).unwrap();
+            for _ in 0..100 {
+                writer.quiescent_sync();
+            }
+            for _ in 0..100 {
+                writer.sync_expedited();
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+    }
+}
+
+#[test]
+fn nested_read_sections() {
+    use rcu::RcuHandle;
+
+    let my_rcu = Qsbr::<Futex>::new();
+    let mut t_handle = my_rcu.register(1).unwrap();
+    let outer = t_handle.read();
+    let inner = t_handle.read();
+    drop(inner);
+    drop(outer);
+    t_handle.quiescent_state();
+    t_handle.sync();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "sync called inside a read side critical section")]
+fn sync_inside_read_panics() {
+    use rcu::RcuHandle;
+
+    let my_rcu = Qsbr::<Futex>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    let outer = t_handle.read();
+    let inner = t_handle.read();
+    drop(inner);
+    t_handle.sync();
+    drop(outer);
+}
+
+#[test]
+fn is_reading_and_check_guard() {
+    use rcu::{check_guard, RcuGuard, RcuHandle};
+
+    let my_rcu = Qsbr::<Futex>::new();
+    let other_rcu = Qsbr::<Futex>::new();
+    assert_ne!(my_rcu.domain_id(), other_rcu.domain_id());
+    let t_handle = my_rcu.register(1).unwrap();
+    assert!(!t_handle.is_reading());
+    let guard = t_handle.read();
+    assert!(t_handle.is_reading());
+    assert_eq!(guard.domain_id(), t_handle.domain_id());
+    check_guard(&my_rcu, &guard);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        check_guard(&other_rcu, &guard)
+    }));
+    assert!(result.is_err());
+    drop(guard);
+    assert!(!t_handle.is_reading());
+}
@@ -0,0 +1,65 @@
+use rcu::cds::rcucell::RcuCell;
+use rcu::utils::{Futex, SpinLock};
+use rcu::{mb::Mb, qsbr::Qsbr, RcuHandle, RCU};
+use std::thread;
+
+fn update_cell<R>(id: u64, rcu_handle: &R, cell: &RcuCell<u64, R>)
+where
+    R: RCU,
+{
+    let mut t_handle = rcu_handle.register(id).unwrap();
+    for _ in 0..10 {
+        let guard = t_handle.read();
+        let current = *cell.read(&guard);
+        assert!(current.is_multiple_of(2));
+        drop(guard);
+        let old = cell.update(|old| old + 2, &mut t_handle);
+        assert!(old.is_multiple_of(2));
+        t_handle.quiescent_state();
+    }
+}
+
+#[test]
+fn single_threaded_cell() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let cell = RcuCell::<String, Qsbr<Futex>>::new("old".to_string());
+    let mut t_handle = my_rcu.register(1).unwrap();
+    let guard = t_handle.read();
+    assert_eq!(cell.read(&guard), "old");
+    drop(guard);
+    assert_eq!(cell.replace("new".to_string(), &mut t_handle), "old");
+    assert_eq!(
+        cell.update(|old| format!("{}er", old), &mut t_handle),
+        "new"
+    );
+    let guard = t_handle.read();
+    assert_eq!(cell.read(&guard), "newer");
+}
+
+#[test]
+fn multi_threaded_cell_qsbr() {
+    let my_rcu = Qsbr::<SpinLock>::new();
+    let cell = RcuCell::<u64, Qsbr<SpinLock>>::new(0);
+    thread::scope(|s| {
+        for i in 0..10 {
+            let (handle, cell) = (&my_rcu, &cell);
+            s.spawn(move || update_cell(i, handle, cell));
+        }
+    });
+    let mut t_handle = my_rcu.register(100).unwrap();
+    assert_eq!(cell.replace(0, &mut t_handle), 200);
+}
+
+#[test]
+fn multi_threaded_cell_mb() {
+    let my_rcu = Mb::<Futex>::new();
+    let cell = RcuCell::<u64, Mb<Futex>>::new(0);
+    thread::scope(|s| {
+        for i in 0..10 {
+            let (handle, cell) = (&my_rcu, &cell);
+            s.spawn(move || update_cell(i, handle, cell));
+        }
+    });
+    let mut t_handle = my_rcu.register(100).unwrap();
+    assert_eq!(cell.replace(0, &mut t_handle), 200);
+}
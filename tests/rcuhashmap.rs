@@ -0,0 +1,91 @@
+use rcu::cds::rcuhashmap::RcuHashMap;
+use rcu::utils::{Futex, Lock, SpinLock};
+use rcu::{mb::Mb, qsbr::Qsbr, RcuHandle, RCU};
+use std::thread;
+
+fn modify_map<R, L>(id: u64, rcu_handle: &R, map: &RcuHashMap<u64, u64, R, L>)
+where
+    R: RCU,
+    L: for<'a> Lock<'a>,
+{
+    let mut t_handle = rcu_handle.register(id).unwrap();
+    let keys = (id * 1000)..(id * 1000 + 200);
+    for k in keys.clone() {
+        assert!(map.insert(k, k).is_ok());
+    }
+    let guard = t_handle.read();
+    for k in keys.clone() {
+        assert_eq!(map.get(&guard, &k), Some(&k));
+    }
+    drop(guard);
+    for k in keys.clone().step_by(2) {
+        assert_eq!(map.remove(&k, &mut t_handle), Some(k));
+        t_handle.quiescent_state();
+    }
+    let guard = t_handle.read();
+    for k in keys {
+        assert_eq!(map.contains_key(&guard, &k), k % 2 == 1);
+    }
+    drop(guard);
+    t_handle.quiescent_state();
+}
+
+#[test]
+fn single_threaded_map() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let map = RcuHashMap::<String, u32, Qsbr<Futex>, Futex>::new();
+    let mut t_handle = my_rcu.register(1).unwrap();
+    for i in 0..1000 {
+        assert!(map.insert(i.to_string(), i).is_ok());
+    }
+    assert_eq!(map.insert("7".to_string(), 0), Err(("7".to_string(), 0)));
+    assert_eq!(map.len(), 1000);
+    let guard = t_handle.read();
+    for i in 0..1000 {
+        assert_eq!(map.get(&guard, i.to_string().as_str()), Some(&i));
+    }
+    assert_eq!(map.get(&guard, "1000"), None);
+    assert_eq!(map.iter(&guard).count(), 1000);
+    drop(guard);
+    assert_eq!(map.remove("7", &mut t_handle), Some(7));
+    assert_eq!(map.remove("7", &mut t_handle), None);
+    assert_eq!(
+        map.insert_or_replace("8".to_string(), 80, &mut t_handle),
+        Some(8)
+    );
+    assert_eq!(
+        map.insert_or_replace("7".to_string(), 70, &mut t_handle),
+        None
+    );
+    let guard = t_handle.read();
+    assert_eq!(map.get(&guard, "7"), Some(&70));
+    assert_eq!(map.get(&guard, "8"), Some(&80));
+    assert_eq!(map.len(), 1000);
+    assert!(format!("{:?}", map).starts_with("RcuHashMap { len: 1000, buckets: "));
+}
+
+#[test]
+fn multi_threaded_map_qsbr() {
+    let my_rcu = Qsbr::<SpinLock>::new();
+    let map = RcuHashMap::<u64, u64, Qsbr<SpinLock>, SpinLock>::new();
+    thread::scope(|s| {
+        for i in 0..10 {
+            let (handle, map) = (&my_rcu, &map);
+            s.spawn(move || modify_map(i, handle, map));
+        }
+    });
+    assert_eq!(map.len(), 1000);
+}
+
+#[test]
+fn multi_threaded_map_mb() {
+    let my_rcu = Mb::<Futex>::new();
+    let map = RcuHashMap::<u64, u64, Mb<Futex>, Futex>::new();
+    thread::scope(|s| {
+        for i in 0..10 {
+            let (handle, map) = (&my_rcu, &map);
+            s.spawn(move || modify_map(i, handle, map));
+        }
+    });
+    assert_eq!(map.len(), 1000);
+}
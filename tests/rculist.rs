@@ -1,5 +1,9 @@
 use rcu::utils::{Futex, Lock, SpinLock};
-use rcu::{cds::rculist::RcuList, cds::rculist::RcuListIterator, qsbr::Qsbr, RcuHandle, RCU};
+use rcu::{
+    cds::rculist::RcuList, cds::rculist::RcuListIterator, mb::Mb, memb::Memb, qsbr::Qsbr,
+    srcu::Srcu, tree::TreeRcu, RcuHandle, RCU,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
 
 fn modify_rcu<R, L>(id: u64, rcu_handle: &R, list: &RcuList<u32, R, L>)
@@ -7,20 +11,19 @@ where
     R: RCU,
     L: for<'a> Lock<'a>,
 {
-    let mut t_handle = rcu_handle.register(id);
+    let mut t_handle = rcu_handle.register(id).unwrap();
     t_handle.quiescent_state();
     let id = id.try_into().unwrap();
     list.insert(id);
     let guard = t_handle.read();
-    let list_iter = RcuListIterator::new(&guard, list);
-    let elems: Vec<_> = list_iter.collect();
-    assert!(elems.contains(&&id));
+    assert!(list.contains(&guard, &id));
+    // remove syncs, so has to happen outside of the read side critical section
+    drop(guard);
     // test rcu_list drop
     if id % 2 == 0 {
         let my_elem = list.remove(&id, &mut t_handle);
-        assert!(my_elem == id);
+        assert_eq!(my_elem, Some(id));
     }
-    drop(guard);
     t_handle.quiescent_state();
     drop(t_handle);
 }
@@ -90,3 +93,390 @@ fn multi_threaded_list_spin() {
         }
     });
 }
+
+#[test]
+fn multi_threaded_list_mb() {
+    let my_rcu = Mb::<Futex>::new();
+    let my_list = RcuList::<u32, Mb<Futex>, Futex>::new();
+    thread::scope(|s| {
+        for i in 0..20 {
+            let handle = &my_rcu;
+            let list = &my_list;
+            thread::Builder::new()
+                .name(format!("child-{}", i))
+                .spawn_scoped(s, move || {
+                    modify_rcu(i, handle, list);
+                })
+                .unwrap();
+        }
+    });
+}
+
+#[test]
+fn multi_threaded_list_memb() {
+    let my_rcu = Memb::<Futex>::new();
+    let my_list = RcuList::<u32, Memb<Futex>, Futex>::new();
+    thread::scope(|s| {
+        for i in 0..20 {
+            let handle = &my_rcu;
+            let list = &my_list;
+            thread::Builder::new()
+                .name(format!("child-{}", i))
+                .spawn_scoped(s, move || {
+                    modify_rcu(i, handle, list);
+                })
+                .unwrap();
+        }
+    });
+}
+
+#[test]
+fn multi_threaded_list_srcu() {
+    let my_rcu = Srcu::<Futex>::new();
+    let my_list = RcuList::<u32, Srcu<Futex>, Futex>::new();
+    thread::scope(|s| {
+        for i in 0..20 {
+            let handle = &my_rcu;
+            let list = &my_list;
+            thread::Builder::new()
+                .name(format!("child-{}", i))
+                .spawn_scoped(s, move || {
+                    modify_rcu(i, handle, list);
+                })
+                .unwrap();
+        }
+    });
+}
+
+#[test]
+fn multi_threaded_list_tree() {
+    let my_rcu = TreeRcu::<Futex>::new();
+    let my_list = RcuList::<u32, TreeRcu<Futex>, Futex>::new();
+    thread::scope(|s| {
+        for i in 0..20 {
+            let handle = &my_rcu;
+            let list = &my_list;
+            thread::Builder::new()
+                .name(format!("child-{}", i))
+                .spawn_scoped(s, move || {
+                    modify_rcu(i, handle, list);
+                })
+                .unwrap();
+        }
+    });
+}
+
+#[test]
+fn insert_keeps_list_sorted() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<u32, Qsbr<Futex>, Futex>::new();
+    for i in [5, 3, 9, 1, 7] {
+        my_list.insert(i);
+    }
+    assert_eq!(my_list.try_insert(3), Err(3));
+    assert!(my_list.try_insert(4).is_ok());
+    assert!(my_list.is_strictly_ordered());
+    let t_handle = my_rcu.register(1).unwrap();
+    let guard = t_handle.read();
+    let elems: Vec<_> = RcuListIterator::new(&guard, &my_list).copied().collect();
+    assert_eq!(elems, vec![1, 3, 4, 5, 7, 9]);
+}
+
+#[test]
+fn lookups_use_sorted_order() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<u32, Qsbr<Futex>, Futex>::new();
+    for i in [10, 20, 30, 40] {
+        my_list.insert(i);
+    }
+    let t_handle = my_rcu.register(1).unwrap();
+    let guard = t_handle.read();
+    assert_eq!(my_list.get(&guard, &30), Some(&30));
+    assert_eq!(my_list.get(&guard, &25), None);
+    assert!(my_list.contains(&guard, &10));
+    assert!(!my_list.contains(&guard, &50));
+    // find stops at the first element past what it looks for
+    let mut visited = Vec::new();
+    let found = my_list.find(&guard, |e| {
+        visited.push(*e);
+        (*e / 10).cmp(&2)
+    });
+    assert_eq!(found, Some(&20));
+    assert_eq!(visited, vec![10, 20]);
+    visited.clear();
+    assert_eq!(
+        my_list.find(&guard, |e| {
+            visited.push(*e);
+            e.cmp(&15)
+        }),
+        None
+    );
+    assert_eq!(visited, vec![10, 20]);
+}
+
+#[test]
+fn remove_variants() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<(u32, char), Qsbr<Futex>, Futex>::new();
+    for (i, c) in ['a', 'b', 'c', 'd', 'e', 'f'].into_iter().enumerate() {
+        my_list.insert((i as u32, c));
+    }
+    let mut t_handle = my_rcu.register(1).unwrap();
+    assert_eq!(my_list.remove(&(1, 'b'), &mut t_handle), Some((1, 'b')));
+    assert_eq!(my_list.remove(&(1, 'b'), &mut t_handle), None);
+    assert_eq!(
+        my_list.remove_by_key(&4, |e| e.0, &mut t_handle),
+        Some((4, 'e'))
+    );
+    assert_eq!(my_list.remove_by_key(&4, |e| e.0, &mut t_handle), None);
+    assert_eq!(
+        my_list.remove_if(|e| e.0 % 2 == 0, &mut t_handle),
+        vec![(0, 'a'), (2, 'c')]
+    );
+    assert!(my_list.remove_if(|e| e.0 == 9, &mut t_handle).is_empty());
+    assert!(my_list.is_strictly_ordered());
+    let guard = t_handle.read();
+    let elems: Vec<_> = RcuListIterator::new(&guard, &my_list).copied().collect();
+    assert_eq!(elems, vec![(3, 'd'), (5, 'f')]);
+}
+
+#[test]
+fn replace_is_never_missing_for_readers() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<(u32, u32), Qsbr<Futex>, Futex>::new();
+    for key in 0..3 {
+        my_list.insert((key, 0));
+    }
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        for id in 1..4 {
+            let (rcu, list, done) = (&my_rcu, &my_list, &done);
+            s.spawn(move || {
+                let mut t_handle = rcu.register(id).unwrap();
+                while !done.load(Ordering::Relaxed) {
+                    let guard = t_handle.read();
+                    assert!(list.find(&guard, |e| e.0.cmp(&1)).is_some());
+                    drop(guard);
+                    t_handle.quiescent_state();
+                }
+            });
+        }
+        let mut t_handle = my_rcu.register(0).unwrap();
+        for version in 0..100 {
+            let old = my_list.replace(&(1, version), (1, version + 1), &mut t_handle);
+            assert_eq!(old, Ok((1, version)));
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+    assert!(my_list.is_strictly_ordered());
+    let t_handle = my_rcu.register(0).unwrap();
+    let guard = t_handle.read();
+    let elems: Vec<_> = RcuListIterator::new(&guard, &my_list).copied().collect();
+    assert_eq!(elems, vec![(0, 0), (1, 100), (2, 0)]);
+}
+
+#[test]
+#[should_panic(expected = "same position")]
+fn replace_must_keep_order() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<u32, Qsbr<Futex>, Futex>::new();
+    for i in [1, 2, 3] {
+        my_list.insert(i);
+    }
+    let mut t_handle = my_rcu.register(1).unwrap();
+    let _ = my_list.replace(&2, 5, &mut t_handle);
+}
+
+#[test]
+fn replace_missing_hands_new_back() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<u32, Qsbr<Futex>, Futex>::new();
+    for i in [1, 3] {
+        my_list.insert(i);
+    }
+    let mut t_handle = my_rcu.register(1).unwrap();
+    assert_eq!(my_list.replace(&2, 2, &mut t_handle), Err(2));
+    assert_eq!(my_list.replace(&3, 3, &mut t_handle), Ok(3));
+    assert!(my_list.is_strictly_ordered());
+}
+
+#[test]
+fn double_ended_iteration() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<u32, Qsbr<Futex>, Futex>::new();
+    for i in [3, 1, 4, 5, 2] {
+        my_list.insert(i);
+    }
+    let t_handle = my_rcu.register(1).unwrap();
+    let guard = t_handle.read();
+    let rev: Vec<_> = RcuListIterator::new(&guard, &my_list)
+        .rev()
+        .copied()
+        .collect();
+    assert_eq!(rev, vec![5, 4, 3, 2, 1]);
+    // the two ends stop where they meet
+    let mut iter = RcuListIterator::new(&guard, &my_list);
+    assert_eq!(iter.next(), Some(&1));
+    assert_eq!(iter.next_back(), Some(&5));
+    assert_eq!(iter.next_back(), Some(&4));
+    assert_eq!(iter.next(), Some(&2));
+    assert_eq!(iter.next(), Some(&3));
+    assert_eq!(iter.next_back(), None);
+    assert_eq!(iter.next(), None);
+}
+
+#[test]
+fn cursor_moves_and_seeks() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<u32, Qsbr<Futex>, Futex>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    let guard = t_handle.read();
+    assert_eq!(my_list.cursor_front(&guard).current(), None);
+    drop(guard);
+    for i in [10, 20, 30] {
+        my_list.insert(i);
+    }
+    let guard = t_handle.read();
+    let mut cursor = my_list.cursor_front(&guard);
+    assert_eq!(cursor.current(), Some(&10));
+    assert_eq!(cursor.peek_prev(), None);
+    assert_eq!(cursor.peek_next(), Some(&20));
+    cursor.move_prev();
+    assert_eq!(cursor.current(), None);
+    // the ghost position wraps around to either end
+    assert_eq!(cursor.peek_next(), Some(&10));
+    assert_eq!(cursor.peek_prev(), Some(&30));
+    cursor.seek(&25);
+    assert_eq!(cursor.current(), Some(&30));
+    cursor.seek(&20);
+    assert_eq!(cursor.current(), Some(&20));
+    cursor.seek(&5);
+    assert_eq!(cursor.current(), Some(&10));
+    cursor.seek(&31);
+    assert_eq!(cursor.current(), None);
+    let mut cursor = my_list.cursor_back(&guard);
+    assert_eq!(cursor.current(), Some(&30));
+    cursor.move_prev();
+    cursor.move_prev();
+    assert_eq!(cursor.current(), Some(&10));
+    cursor.move_next();
+    assert_eq!(cursor.current(), Some(&20));
+}
+
+#[test]
+fn reverse_walks_during_updates() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<u32, Qsbr<Futex>, Futex>::new();
+    // even elements stay in the list, odd ones come and go
+    for i in (0..20).step_by(2) {
+        my_list.insert(i);
+    }
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        for id in 1..4 {
+            let (rcu, list, done) = (&my_rcu, &my_list, &done);
+            s.spawn(move || {
+                let mut t_handle = rcu.register(id).unwrap();
+                while !done.load(Ordering::Relaxed) {
+                    let guard = t_handle.read();
+                    let rev: Vec<_> = RcuListIterator::new(&guard, list).rev().collect();
+                    assert!(rev.windows(2).all(|w| w[0] > w[1]));
+                    assert_eq!(rev.iter().filter(|e| **e % 2 == 0).count(), 10);
+                    let mut cursor = list.cursor_back(&guard);
+                    let mut evens = 0;
+                    while let Some(e) = cursor.current() {
+                        evens += (e % 2 == 0) as u32;
+                        assert!(cursor.peek_prev().is_none_or(|p| p < e));
+                        cursor.move_prev();
+                    }
+                    assert_eq!(evens, 10);
+                    drop(guard);
+                    t_handle.quiescent_state();
+                }
+            });
+        }
+        let mut t_handle = my_rcu.register(0).unwrap();
+        for round in 0..20 {
+            for i in (1..21).step_by(2) {
+                my_list.insert(i);
+            }
+            if round % 2 == 0 {
+                my_list.remove_if(|e| e % 2 == 1, &mut t_handle);
+            } else {
+                for i in (1..21).step_by(2).rev() {
+                    my_list.remove(&i, &mut t_handle);
+                }
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+    assert!(my_list.is_strictly_ordered());
+}
+
+#[test]
+fn racing_removers() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<u32, Qsbr<Futex>, Futex>::new();
+    for i in 0..10 {
+        my_list.insert(i);
+    }
+    let removed: u32 = thread::scope(|s| {
+        let threads: Vec<_> = (0..8)
+            .map(|id| {
+                let (rcu, list) = (&my_rcu, &my_list);
+                s.spawn(move || {
+                    let mut t_handle = rcu.register(id).unwrap();
+                    (0..10)
+                        .filter(|i| list.remove(i, &mut t_handle).is_some())
+                        .count() as u32
+                })
+            })
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).sum()
+    });
+    // every element is removed exactly once
+    assert_eq!(removed, 10);
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "different rcu instance")]
+fn guard_from_other_rcu_panics() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let other_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<u32, Qsbr<Futex>, Futex>::new();
+    my_list.insert(1);
+    let t_handle = my_rcu.register(1).unwrap();
+    let guard = t_handle.read();
+    assert_eq!(RcuListIterator::new(&guard, &my_list).count(), 1);
+    drop(guard);
+    let other_handle = other_rcu.register(1).unwrap();
+    let other_guard = other_handle.read();
+    RcuListIterator::new(&other_guard, &my_list).count();
+}
+
+#[test]
+#[should_panic(expected = "different rcu instance")]
+fn list_bound_to_rcu_rejects_other_guards() {
+    let my_rcu = Qsbr::<Futex>::new();
+    let other_rcu = Qsbr::<Futex>::new();
+    let my_list = RcuList::<u32, Qsbr<Futex>, Futex>::new_in(&my_rcu);
+    my_list.insert(1);
+    let other_handle = other_rcu.register(1).unwrap();
+    let other_guard = other_handle.read();
+    RcuListIterator::new(&other_guard, &my_list).count();
+}
+
+#[test]
+fn locks_report_being_held() {
+    fn check<L: for<'a> Lock<'a>>() {
+        let lock = L::new();
+        assert!(!lock.is_locked());
+        let guard = lock.lock();
+        assert!(lock.is_locked());
+        drop(guard);
+        assert!(!lock.is_locked());
+    }
+    check::<Futex>();
+    check::<SpinLock>();
+}
@@ -0,0 +1,94 @@
+use rcu::srcu::Srcu;
+use rcu::utils::{Futex, SpinLock};
+use rcu::{RcuHandle, RCU};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn single_threaded_sync() {
+    let my_rcu = Srcu::<Futex>::new();
+    let t_handle = my_rcu.register(1).unwrap();
+    t_handle.sync();
+    let guard = t_handle.read();
+    assert!(t_handle.is_reading());
+    drop(guard);
+    t_handle.sync();
+}
+
+#[test]
+fn multi_threaded_register_spin() {
+    let my_rcu = Srcu::<SpinLock>::new();
+    thread::scope(|s| {
+        for i in 0..20 {
+            let handle = &my_rcu;
+            s.spawn(move || {
+                let t_handle = handle.register(i).unwrap();
+                let _guard = t_handle.read();
+            });
+        }
+    });
+}
+
+#[test]
+fn nested_reads_across_flips() {
+    let my_rcu = Srcu::<Futex>::new();
+    let reader = my_rcu.register(1).unwrap();
+    let outer = reader.read();
+    let idx = outer.index();
+    thread::scope(|s| {
+        s.spawn(|| {
+            let writer = my_rcu.register(2).unwrap();
+            writer.sync();
+        });
+        // the sync blocks on outer, after its first flip new readers use the other index and
+        // don't hold it up
+        loop {
+            let inner = reader.read();
+            if inner.index() != idx {
+                break;
+            }
+            drop(inner);
+            thread::sleep(Duration::from_millis(1));
+        }
+        drop(outer);
+    });
+    assert!(!reader.is_reading());
+}
+
+#[test]
+fn sync_waits_for_blocked_reader() {
+    let my_rcu = Srcu::<Futex>::new();
+    let reading = AtomicBool::new(false);
+    let synced = AtomicBool::new(false);
+    thread::scope(|s| {
+        let reader = my_rcu.register(1).unwrap();
+        let guard = reader.read();
+        s.spawn(|| {
+            let writer = my_rcu.register(2).unwrap();
+            while !reading.load(Ordering::Acquire) {
+                std::hint::spin_loop();
+            }
+            writer.sync();
+            synced.store(true, Ordering::Release);
+        });
+        reading.store(true, Ordering::Release);
+        // readers may block inside a critical section
+        thread::sleep(Duration::from_millis(50));
+        assert!(!synced.load(Ordering::Acquire));
+        drop(guard);
+    });
+    assert!(synced.load(Ordering::Acquire));
+}
+
+#[test]
+fn domains_are_independent() {
+    let slow_domain = Srcu::<Futex>::new();
+    let fast_domain = Srcu::<Futex>::new();
+    let slow_reader = slow_domain.register(1).unwrap();
+    let _guard = slow_reader.read();
+    // a reader blocked in one domain doesn't hold up syncs in another
+    let writer = fast_domain.register(1).unwrap();
+    writer.sync();
+    assert_ne!(slow_domain.domain_id(), fast_domain.domain_id());
+}
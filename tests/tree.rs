@@ -0,0 +1,112 @@
+use rcu::tree::TreeRcu;
+use rcu::utils::{Futex, SpinLock};
+use rcu::{RcuHandle, RegisterError, SleepingRcu, RCU};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Barrier;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn single_threaded_sync() {
+    let my_rcu = TreeRcu::<Futex>::new();
+    let mut t_handle = my_rcu.register(1).unwrap();
+    t_handle.sync();
+    t_handle.quiescent_state();
+    t_handle.quiescent_sync();
+    assert_eq!(
+        my_rcu.register(1).err(),
+        Some(RegisterError::DuplicateId(1))
+    );
+}
+
+#[test]
+fn sync_waits_for_quiescent_state() {
+    let my_rcu = TreeRcu::<Futex>::new();
+    let registered = Barrier::new(2);
+    let synced = AtomicBool::new(false);
+    thread::scope(|s| {
+        let mut reader = my_rcu.register(1).unwrap();
+        s.spawn(|| {
+            let writer = my_rcu.register(2).unwrap();
+            registered.wait();
+            writer.sync();
+            synced.store(true, Ordering::Release);
+        });
+        registered.wait();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!synced.load(Ordering::Acquire));
+        reader.quiescent_state();
+        while !synced.load(Ordering::Acquire) {
+            reader.quiescent_state();
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+}
+
+#[test]
+fn sleeping_threads_dont_block_sync() {
+    let my_rcu = TreeRcu::<Futex>::new();
+    let reader = my_rcu.register(1).unwrap();
+    let sleeper = reader.sleep();
+    let writer = my_rcu.register(2).unwrap();
+    writer.sync();
+    let mut reader = sleeper.wake();
+    reader.quiescent_state();
+}
+
+#[test]
+fn many_threads_across_leaves() {
+    const THREADS: u64 = 200;
+    let my_rcu = TreeRcu::<SpinLock>::new();
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        for id in 1..THREADS {
+            let (my_rcu, done) = (&my_rcu, &done);
+            s.spawn(move || {
+                let mut reader = my_rcu.register(id).unwrap();
+                while !done.load(Ordering::Relaxed) {
+                    let guard = reader.read();
+                    drop(guard);
+                    reader.quiescent_state();
+                    thread::yield_now();
+                }
+            });
+        }
+        let writer = my_rcu.register(THREADS).unwrap();
+        for _ in 0..50 {
+            writer.sync();
+        }
+        done.store(true, Ordering::Relaxed);
+    });
+}
+
+#[test]
+fn shared_handle_reads() {
+    let my_rcu = TreeRcu::<Futex>::new();
+    let mut handle = my_rcu.register(1).unwrap();
+    thread::scope(|s| {
+        for _ in 0..4 {
+            let handle = &handle;
+            s.spawn(move || {
+                for _ in 0..10000 {
+                    let outer = handle.read();
+                    let inner = handle.read();
+                    drop(inner);
+                    drop(outer);
+                }
+            });
+        }
+    });
+    assert!(!handle.is_reading());
+    handle.quiescent_state();
+    handle.sync();
+}
+
+#[test]
+fn register_fails_when_full() {
+    let my_rcu = TreeRcu::<Futex>::new();
+    let handles: Vec<_> = (0..4096).map(|id| my_rcu.register(id).unwrap()).collect();
+    assert_eq!(my_rcu.register(4096).err(), Some(RegisterError::Full));
+    drop(handles);
+    my_rcu.register(4096).unwrap().sync();
+}